@@ -0,0 +1,89 @@
+//! Compares the throughput of a blocking Redis `INCR` with an async multiplexed
+//! connection, at several concurrency levels.
+//!
+//! Requires a reachable Redis (`REDIS_URL`, default `redis://localhost:6379`), e.g. a
+//! throwaway container started with `docker run --rm -p 6379:6379 redis:7`, and runs with:
+//!
+//! ```sh
+//! cargo bench --features redis-bench --bench redis_concurrency
+//! ```
+//!
+//! The benchmark increments its own `bench:incr:count` key and never touches the
+//! service counter.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis::Client;
+use tokio::runtime::Runtime;
+
+const BENCH_KEY: &str = "bench:incr:count";
+const CONCURRENCY_LEVELS: [usize; 4] = [1, 8, 64, 256];
+
+
+fn redis_url() -> String {
+    std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string())
+}
+
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .expect("failed to build Tokio runtime")
+}
+
+
+/// Each task runs a blocking `INCR` on its worker thread.
+async fn blocking_incr(client: &Client, concurrency: usize) {
+    let tasks: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let mut cn = client.clone();
+            tokio::spawn(async move {
+                redis::cmd("INCR").arg(BENCH_KEY).query::<i64>(&mut cn).unwrap();
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+
+/// Each task awaits an `INCR` on a shared multiplexed connection.
+async fn async_incr(connection: &redis::aio::MultiplexedConnection, concurrency: usize) {
+    let tasks: Vec<_> = (0..concurrency)
+        .map(|_| {
+            let mut con = connection.clone();
+            tokio::spawn(async move {
+                redis::cmd("INCR").arg(BENCH_KEY).query_async::<i64>(&mut con).await.unwrap();
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+
+fn bench_redis_incr(c: &mut Criterion) {
+    let rt = runtime();
+    let client = Client::open(redis_url()).expect("invalid REDIS_URL");
+    let connection = rt
+        .block_on(client.get_multiplexed_async_connection())
+        .expect("failed to connect to Redis");
+
+    let mut group = c.benchmark_group("redis_incr");
+    for concurrency in CONCURRENCY_LEVELS {
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(BenchmarkId::new("blocking", concurrency), &concurrency, |b, &n| {
+            b.to_async(&rt).iter(|| blocking_incr(&client, n));
+        });
+        group.bench_with_input(BenchmarkId::new("async", concurrency), &concurrency, |b, &n| {
+            b.to_async(&rt).iter(|| async_incr(&connection, n));
+        });
+    }
+    group.finish();
+}
+
+
+criterion_group!(benches, bench_redis_incr);
+criterion_main!(benches);
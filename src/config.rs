@@ -1,9 +1,25 @@
 //! This module defines the configuration structures for the key generation service.
 //! It includes configurations for the service itself, different types of key generators,
 //! logging, and tracing. The configurations are loaded from environment variables.
+//!
+//! The variables can also be set in a TOML file named by `CONFIG_FILE`, where tables
+//! group them by prefix: `type = "redis"` in a `[generator]` table sets `GENERATOR_TYPE`.
+//! A variable set in the environment takes precedence over the file.
 
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::path::Path;
+use std::sync::OnceLock;
 use anyhow::{anyhow, Result};
+use chrono_tz::Tz;
+use tracing::{info, warn};
+use crate::generator::{max_number, number_digits};
+use crate::generator::encoding::Encoding;
+use crate::generator::snowflake::MAX_WORKER_ID;
+
+/// The number of distinct Snowflake IDs, which take 63 bits.
+const SNOWFLAKE_ID_SPACE: u128 = 1 << 63;
 
 /// `GenerationKeyServiceConfig` holds the main configuration for the service.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -12,6 +28,72 @@ pub struct GenerationKeyServiceConfig {
     pub listen_port: u16,
     /// The configuration for the chosen key generator.
     pub generator_config: GeneratorConfig,
+    /// The region tag placed in the leading character of every key, if any.
+    pub region_tag: Option<char>,
+    /// Whether startup must fail when OpenTelemetry cannot be initialized.
+    pub telemetry_required: bool,
+    /// The OTLP collector that must accept a connection at startup when telemetry is required.
+    pub telemetry_endpoint: String,
+    /// The configuration of the per-day issuance ledger, if enabled.
+    pub ledger_config: Option<LedgerConfig>,
+    /// Whether likely misconfigurations are errors instead of warnings.
+    pub strict: bool,
+    /// Whether keys must sort lexicographically in issuance order.
+    pub keys_time_ordered: bool,
+    /// The options of the gRPC service layer.
+    pub service_config: ServiceConfig,
+    /// Whether a key is generated and validated at startup.
+    pub warmup: bool,
+    /// The backend receiving the service metrics.
+    pub metrics_config: MetricsConfig,
+    /// The rules rejecting generated keys, if any.
+    pub key_filter_config: Option<KeyFilterConfig>,
+    /// The additional ports, each serving its own generator.
+    pub extra_listeners: Vec<ListenerConfig>,
+    /// How many recent keys are checked for duplicates, if development duplicate detection is enabled.
+    pub duplicate_detection: Option<usize>,
+    /// Whether the following ports are tried when a listen port is already in use.
+    pub port_auto_increment: bool,
+    /// The smallest generator capacity the service may start with, if any.
+    pub expected_min_capacity: Option<u128>,
+    /// The cap on keys issued per time window, if enabled.
+    pub window_limit_config: Option<WindowLimitConfig>,
+    /// The external service checking random keys for uniqueness, if enabled.
+    pub uniqueness_config: Option<UniquenessConfig>,
+    /// How many digits counter keys may grow by once the keyspace is exhausted, if `AUTO_EXPAND` is enabled.
+    pub auto_expand: Option<usize>,
+    /// The encoding of the keys, from `KEY_ENCODING` and `KEY_CHECK_SYMBOL`.
+    pub encoding: Encoding,
+    /// The alphabet replacing the default base 62 alphabet, if any.
+    pub alphabet: Option<String>,
+    /// Whether keys start with the scheme prefix of their generator family.
+    pub scheme_prefix: bool,
+    /// How long shutdown waits for in-flight requests to complete, in seconds.
+    pub shutdown_timeout_secs: u64,
+    /// Whether the gRPC server reflection service is registered.
+    pub enable_reflection: bool,
+}
+
+
+/// `ListenerConfig` holds an additional port and the generator it serves.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ListenerConfig {
+    /// The port on which the additional gRPC server will listen.
+    pub port: u16,
+    /// The configuration for the generator served on that port.
+    pub generator_config: GeneratorConfig,
+}
+
+
+/// `ServiceConfig` holds the options of the gRPC service layer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ServiceConfig {
+    /// Whether operational metadata is attached to every `generate_key` response.
+    pub response_metadata: bool,
+    /// The identifier of this instance, reported in the response metadata.
+    pub instance_id: String,
+    /// The `GENERATOR_TYPE` of the active generator.
+    pub generator_type: &'static str,
 }
 
 
@@ -19,11 +101,42 @@ pub struct GenerationKeyServiceConfig {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum GeneratorConfig {
     /// A generator that produces random keys.
-    Random,
+    Random(RandomConfig),
     /// A generator that uses Redis to produce incremental keys.
     Redis(RedisConfig),
     /// A generator that uses a primitive root calculation with Redis.
     PrimitiveRootRedis(RedisConfig, PrimitiveConfig),
+    /// A generator that uses Redis with a warm standby Redis for failover.
+    StandbyRedis(RedisConfig, StandbyConfig),
+    /// A break-glass generator serving keys from a pre-loaded pool.
+    MaintenancePool(MaintenancePoolConfig),
+    /// A test generator returning keys from a fixed list.
+    SequenceFixed(SequenceFixedConfig),
+    /// A generator returning UUIDs.
+    Uuid(UuidConfig),
+    /// A generator composing Snowflake-style IDs.
+    Snowflake(SnowflakeConfig),
+    /// A generator returning ULIDs.
+    Ulid,
+    /// A generator handing out Redis counter values reserved in blocks.
+    BufferedRedis(RedisConfig, BufferConfig),
+    /// A generator returning nanoids.
+    Nanoid(NanoidConfig),
+}
+
+/// `RandomConfig` holds the configuration for the random generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct RandomConfig {
+    /// The Redis server holding the blocklist of values never to emit, if enabled.
+    pub blocklist: Option<RedisConfig>,
+    /// How many times a blocked or recently issued value is re-rolled before giving up.
+    pub max_rerolls: usize,
+    /// The smallest keyspace considered safe for random generation.
+    pub min_keyspace: u128,
+    /// How many recently issued values are re-rolled if drawn again, 0 to disable.
+    pub dedup_window: usize,
+    /// The seed of the random number generator, or `None` to use the thread RNG.
+    pub seed: Option<u64>,
 }
 
 /// `RedisConfig` holds the configuration for connecting to Redis.
@@ -31,6 +144,43 @@ pub enum GeneratorConfig {
 pub struct RedisConfig {
     /// The URL of the Redis server.
     pub url: String,
+    /// Whether counter values not higher than the last one seen are rejected.
+    pub monotonic_guard: bool,
+    /// The maximum number of pooled connections.
+    pub pool_size: usize,
+    /// How far below the highest counter value seen a value means the counter was lost, if detected.
+    pub loss_threshold: Option<u128>,
+    /// The file persisting the highest counter value seen across restarts, if any.
+    pub high_water_file: Option<String>,
+    /// The retries of commands failing with a connection error.
+    pub retry: RetryConfig,
+    /// The coalescing of concurrent increments into one `INCRBY`, if enabled.
+    pub coalesce: Option<CoalesceConfig>,
+}
+
+/// `RetryConfig` holds the retry policy for transient Redis failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RetryConfig {
+    /// How many times a command is attempted in total, including the first attempt.
+    pub max_attempts: u32,
+    /// The delay before the first retry, in milliseconds. Each further retry waits twice as long.
+    pub base_delay_ms: u64,
+}
+
+/// `CoalesceConfig` holds how concurrent counter increments are merged into one `INCRBY`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CoalesceConfig {
+    /// How long the first increment of a batch waits for others, in microseconds.
+    pub window_us: u64,
+    /// The maximum number of increments merged into one `INCRBY`.
+    pub max_batch: usize,
+}
+
+impl Default for RetryConfig {
+    /// A policy attempting every command once.
+    fn default() -> Self {
+        RetryConfig { max_attempts: 1, base_delay_ms: 0 }
+    }
 }
 
 /// `PrimitiveConfig` holds the configuration for the primitive root generator.
@@ -44,6 +194,170 @@ pub struct PrimitiveConfig {
     pub primitive_root: u128,
 }
 
+/// `StandbyConfig` holds the configuration for the warm standby Redis.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StandbyConfig {
+    /// The URL of the standby Redis server.
+    pub url: String,
+    /// How often, in milliseconds, the primary counter is mirrored.
+    pub mirror_interval_ms: u64,
+    /// How far past the last mirrored value the standby starts on failover.
+    pub failover_margin: u128,
+}
+
+/// `BufferConfig` holds the configuration for the buffered Redis generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BufferConfig {
+    /// How many counter values are reserved at once.
+    pub size: usize,
+    /// How few buffered values trigger the reservation of the next block.
+    pub refill_below: usize,
+}
+
+/// `MaintenancePoolConfig` holds the configuration for the maintenance pool generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MaintenancePoolConfig {
+    /// The path of the file holding the pool keys, one per line.
+    pub path: String,
+}
+
+/// `SequenceFixedConfig` holds the configuration for the fixed sequence test generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SequenceFixedConfig {
+    /// The keys returned, in order.
+    pub keys: Vec<String>,
+    /// What happens once every key has been returned.
+    pub end: SequenceEnd,
+}
+
+/// `SequenceEnd` defines what the fixed sequence generator does past its last key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SequenceEnd {
+    /// Start again from the first key.
+    Wrap,
+    /// Fail every further request.
+    Error,
+}
+
+/// `UuidConfig` holds the configuration for the UUID generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UuidConfig {
+    /// The UUID version to generate.
+    pub version: UuidVersion,
+}
+
+/// `UuidVersion` defines the UUID versions the UUID generator supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UuidVersion {
+    /// Random UUIDs.
+    V4,
+    /// Time-ordered UUIDs, starting with a millisecond timestamp.
+    V7,
+}
+
+/// `NanoidConfig` holds the configuration for the nanoid generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NanoidConfig {
+    /// The number of characters of a key.
+    pub length: usize,
+    /// The symbols keys are drawn from.
+    pub alphabet: Vec<char>,
+}
+
+impl Default for NanoidConfig {
+    /// 21 characters over the URL-safe alphabet of letters, digits, `_` and `-`.
+    fn default() -> Self {
+        NanoidConfig { length: 21, alphabet: nanoid::alphabet::SAFE.to_vec() }
+    }
+}
+
+/// `SnowflakeConfig` holds the configuration for the Snowflake generator.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SnowflakeConfig {
+    /// The id of this instance, distinct across instances, from 0 to 1023.
+    pub worker_id: u64,
+}
+
+/// `LedgerConfig` holds the configuration for the per-day issuance ledger.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LedgerConfig {
+    /// The Redis server storing the per-day counters.
+    pub redis_config: RedisConfig,
+    /// The timezone deciding which calendar day a key is counted on.
+    pub timezone: Tz,
+}
+
+/// `WindowLimitConfig` holds the cap on keys issued per time window.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct WindowLimitConfig {
+    /// The Redis server counting the keys issued in the window.
+    pub redis_config: RedisConfig,
+    /// How many keys may be issued per window.
+    pub limit: u64,
+    /// The length of a window, in seconds.
+    pub window_secs: u64,
+}
+
+/// `UniquenessConfig` holds the external service tracking issued keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UniquenessConfig {
+    /// The base URL of the service, asked with `GET <url>/<key>`.
+    pub url: String,
+    /// Whether keys are issued unchecked while the service is unavailable.
+    pub fail_open: bool,
+    /// How long a check may take before the service counts as unavailable, in milliseconds.
+    pub timeout_ms: u64,
+    /// How many times a probable hit is re-rolled before giving up.
+    pub max_rerolls: usize,
+}
+
+/// `KeyFilterConfig` holds the rules rejecting generated keys.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyFilterConfig {
+    /// Keys matching this regex are re-rolled.
+    pub reject_regex: Option<String>,
+    /// Keys starting with one of these prefixes, ignoring case, are re-rolled.
+    pub deny_prefixes: Vec<String>,
+    /// The path of a Rhai script whose `accept(key)` function decides whether a key is issued.
+    pub script_path: Option<String>,
+    /// How many operations one script evaluation may run before it is aborted.
+    pub script_max_operations: u64,
+    /// How long one script evaluation may run before it is aborted, in milliseconds.
+    pub script_timeout_ms: u64,
+    /// How many times a rejected key is re-rolled before giving up.
+    pub max_rerolls: usize,
+}
+
+/// `MetricsConfig` selects the backend receiving the service metrics.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub enum MetricsConfig {
+    /// Metrics are recorded nowhere.
+    #[default]
+    None,
+    /// Metrics are pushed to a StatsD (or DogStatsD) agent over UDP.
+    Statsd(StatsdConfig),
+    /// Metrics are exposed for Prometheus to scrape over HTTP.
+    Prometheus(PrometheusConfig),
+}
+
+/// `PrometheusConfig` holds the configuration for the Prometheus exporter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PrometheusConfig {
+    /// The HTTP port serving the metrics at `/metrics`.
+    pub port: u16,
+}
+
+/// `StatsdConfig` holds the configuration for the StatsD exporter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct StatsdConfig {
+    /// The host of the StatsD agent.
+    pub host: String,
+    /// The UDP port of the StatsD agent.
+    pub port: u16,
+    /// The prefix prepended to every metric name.
+    pub prefix: String,
+}
+
 /// `LokiConfig` holds the configuration for connecting to Loki.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LokiConfig {
@@ -67,17 +381,17 @@ impl PrimitiveConfig {
     /// Returns an error if the required environment variables are not set
     /// or if they contain invalid values, otherwise a `PrimitiveConfig`.
     pub fn from_env() -> Result<Self> {
-        let prime = env::var("GENERATOR_PRIME")
+        let prime = var("GENERATOR_PRIME")
             .unwrap_or_else(|_| "1000003".to_string())
             .parse::<u128>()
             .map_err(|_| anyhow!("Invalid prime value"))?;
 
-        let start = env::var("GENERATOR_INCREMENT_START")
+        let start = var("GENERATOR_INCREMENT_START")
             .unwrap_or_else(|_| "0".to_string())
             .parse::<u128>()
             .map_err(|_| anyhow!("Invalid increment start value"))?;
 
-        let primitive_root = env::var("GENERATOR_PRIME_PRIMITIVE")
+        let primitive_root = var("GENERATOR_PRIME_PRIMITIVE")
             .unwrap_or_else(|_| "2".to_string())
             .parse::<u128>()
             .map_err(|_| anyhow!("Invalid primitive root value"))?;
@@ -88,6 +402,100 @@ impl PrimitiveConfig {
             primitive_root,
         })
     }
+
+    /// Checks the configuration for values that are valid but likely mistakes.
+    ///
+    /// # Arguments
+    ///
+    /// * `strict` - Whether a likely mistake is an error instead of a warning.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error under strict mode if the increment start is not lower than the prime.
+    pub fn validate(&self, strict: bool) -> Result<()> {
+        if self.start >= self.prime {
+            let message = format!(
+                "Increment start {} is not lower than prime {}, it wraps to {}",
+                self.start, self.prime, self.start % self.prime
+            );
+            if strict {
+                return Err(anyhow!(message));
+            }
+            warn!("{message}");
+        }
+        Ok(())
+    }
+}
+
+
+impl RandomConfig {
+    /// Creates a new `RandomConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the variables contain invalid values, otherwise a `RandomConfig`.
+    pub fn from_env() -> Result<Self> {
+        let blocklist = if bool_from_env("RANDOM_BLOCKLIST", false)? {
+            Some(RedisConfig::from_env()?)
+        } else {
+            None
+        };
+
+        let max_rerolls = var("RANDOM_MAX_REROLLS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid max re-rolls value"))?;
+
+        let min_keyspace = var("RANDOM_MIN_KEYSPACE")
+            .unwrap_or_else(|_| "1000000000000".to_string())
+            .parse::<u128>()
+            .map_err(|_| anyhow!("Invalid minimum keyspace value"))?;
+
+        let dedup_window = var("RANDOM_DEDUP_WINDOW")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid dedup window value"))?;
+
+        let seed = match var("RANDOM_SEED") {
+            Ok(seed) => Some(seed.parse::<u64>().map_err(|_| anyhow!("Invalid random seed value"))?),
+            Err(_) => None,
+        };
+
+        Ok(RandomConfig {
+            blocklist,
+            max_rerolls,
+            min_keyspace,
+            dedup_window,
+            seed,
+        })
+    }
+
+    /// Checks that the keyspace is large enough for random keys, since birthday
+    /// collisions become likely after about `sqrt(keyspace)` keys.
+    ///
+    /// # Arguments
+    ///
+    /// * `keyspace` - The number of distinct keys of a region, `62^NUMBER_DIGITS` without a
+    ///   region tag or scheme prefix.
+    /// * `strict` - Whether a too small keyspace is an error instead of a warning.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error under strict mode if the keyspace is below `min_keyspace`.
+    pub fn validate_keyspace(&self, keyspace: u128, strict: bool) -> Result<()> {
+        if keyspace < self.min_keyspace {
+            let message = format!(
+                "Random keyspace {} is below the safety threshold {}, collisions are likely after about {} keys; \
+                 increase NUMBER_DIGITS or use a counter-based generator",
+                keyspace, self.min_keyspace, keyspace.isqrt()
+            );
+            if strict {
+                return Err(anyhow!(message));
+            }
+            warn!("{message}");
+        }
+        Ok(())
+    }
 }
 
 
@@ -96,12 +504,477 @@ impl RedisConfig {
     ///
     /// # Returns
     ///
-    /// Returns an error if the `REDIS_URL` environment variable is not set
-    /// otherwise a `RedisConfig`.
-
+    /// Returns an error if `REDIS_URL_FILE` cannot be read, `REDIS_MONOTONIC_GUARD`
+    /// is not a valid boolean, `REDIS_POOL_SIZE` is not a positive number or
+    /// `COUNTER_LOSS_THRESHOLD`, `REDIS_MAX_RETRIES` or `REDIS_RETRY_BASE_MS` is not
+    /// a number, otherwise a `RedisConfig`.
     pub fn from_env() -> Result<Self> {
+        let pool_size = var("REDIS_POOL_SIZE")
+            .unwrap_or_else(|_| "16".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or_else(|| anyhow!("Invalid Redis pool size value"))?;
+
+        let loss_threshold = match var("COUNTER_LOSS_THRESHOLD") {
+            Ok(threshold) => Some(threshold.parse::<u128>().map_err(|_| anyhow!("Invalid counter loss threshold value"))?),
+            Err(_) => None,
+        };
+
         Ok(RedisConfig {
-            url: env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string()),
+            url: secret_from_env("REDIS_URL")?.unwrap_or_else(|| "redis://localhost:6379".to_string()),
+            monotonic_guard: bool_from_env("REDIS_MONOTONIC_GUARD", false)?,
+            pool_size,
+            loss_threshold,
+            high_water_file: var("COUNTER_HIGH_WATER_FILE").ok(),
+            retry: RetryConfig::from_env()?,
+            coalesce: CoalesceConfig::from_env()?,
+        })
+    }
+}
+
+
+impl RetryConfig {
+    /// Creates a new `RetryConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `REDIS_MAX_RETRIES` or `REDIS_RETRY_BASE_MS` is not a number,
+    /// otherwise a `RetryConfig`.
+    pub fn from_env() -> Result<Self> {
+        let max_attempts = var("REDIS_MAX_RETRIES")
+            .unwrap_or_else(|_| "2".to_string())
+            .parse::<u32>()
+            .ok()
+            .and_then(|retries| retries.checked_add(1))
+            .ok_or_else(|| anyhow!("Invalid Redis max retries value"))?;
+
+        let base_delay_ms = var("REDIS_RETRY_BASE_MS")
+            .unwrap_or_else(|_| "50".to_string())
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Invalid Redis retry base delay value"))?;
+
+        Ok(RetryConfig { max_attempts, base_delay_ms })
+    }
+}
+
+
+impl CoalesceConfig {
+    /// Creates a new `CoalesceConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` unless `REDIS_COALESCE` is enabled, an error if the window or
+    /// maximum batch contain invalid values, otherwise a `CoalesceConfig`.
+    pub fn from_env() -> Result<Option<Self>> {
+        if !bool_from_env("REDIS_COALESCE", false)? {
+            return Ok(None);
+        }
+
+        let window_us = var("REDIS_COALESCE_WINDOW_US")
+            .unwrap_or_else(|_| "200".to_string())
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Invalid Redis coalesce window value"))?;
+
+        let max_batch = var("REDIS_COALESCE_MAX_BATCH")
+            .unwrap_or_else(|_| "128".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|max_batch| *max_batch > 0)
+            .ok_or_else(|| anyhow!("Invalid Redis coalesce max batch value"))?;
+
+        Ok(Some(CoalesceConfig { window_us, max_batch }))
+    }
+}
+
+
+impl StandbyConfig {
+    /// Creates a new `StandbyConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the `STANDBY_REDIS_URL` environment variable is not set
+    /// or if the numeric variables contain invalid values, otherwise a `StandbyConfig`.
+    pub fn from_env() -> Result<Self> {
+        let url = secret_from_env("STANDBY_REDIS_URL")?
+            .ok_or_else(|| anyhow!("STANDBY_REDIS_URL must be set for the standby_redis generator"))?;
+
+        let mirror_interval_ms = var("STANDBY_MIRROR_INTERVAL_MS")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Invalid standby mirror interval value"))?;
+
+        let failover_margin = var("STANDBY_FAILOVER_MARGIN")
+            .unwrap_or_else(|_| "10000".to_string())
+            .parse::<u128>()
+            .map_err(|_| anyhow!("Invalid standby failover margin value"))?;
+
+        Ok(StandbyConfig {
+            url,
+            mirror_interval_ms,
+            failover_margin,
+        })
+    }
+}
+
+
+impl BufferConfig {
+    /// Creates a new `BufferConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `REDIS_BUFFER_SIZE` is not a positive number or
+    /// `REDIS_BUFFER_REFILL_BELOW` is not a number below it, otherwise a `BufferConfig`.
+    pub fn from_env() -> Result<Self> {
+        let size = var("REDIS_BUFFER_SIZE")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or_else(|| anyhow!("Invalid Redis buffer size value"))?;
+
+        let refill_below = match var("REDIS_BUFFER_REFILL_BELOW") {
+            Ok(threshold) => threshold
+                .parse::<usize>()
+                .ok()
+                .filter(|threshold| *threshold < size)
+                .ok_or_else(|| anyhow!("Invalid Redis buffer refill threshold value"))?,
+            Err(_) => size / 10,
+        };
+
+        Ok(BufferConfig { size, refill_below })
+    }
+}
+
+
+impl MaintenancePoolConfig {
+    /// Creates a new `MaintenancePoolConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `MAINTENANCE_POOL_FILE` is not set, otherwise a `MaintenancePoolConfig`.
+    pub fn from_env() -> Result<Self> {
+        let path = var("MAINTENANCE_POOL_FILE")
+            .map_err(|_| anyhow!("MAINTENANCE_POOL_FILE must be set for the maintenance_pool generator"))?;
+
+        Ok(MaintenancePoolConfig { path })
+    }
+}
+
+
+impl SequenceFixedConfig {
+    /// Creates a new `SequenceFixedConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `SEQUENCE_FIXED_KEYS` is not set or empty, or if
+    /// `SEQUENCE_FIXED_END` is neither `wrap` nor `error`, otherwise a `SequenceFixedConfig`.
+    pub fn from_env() -> Result<Self> {
+        let keys: Vec<String> = var("SEQUENCE_FIXED_KEYS")
+            .map_err(|_| anyhow!("SEQUENCE_FIXED_KEYS must be set for the sequence_fixed generator"))?
+            .split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .map(String::from)
+            .collect();
+        if keys.is_empty() {
+            return Err(anyhow!("SEQUENCE_FIXED_KEYS must hold at least one key"));
+        }
+
+        let end = match var("SEQUENCE_FIXED_END").unwrap_or_else(|_| "error".to_string()).as_str() {
+            "wrap" => SequenceEnd::Wrap,
+            "error" => SequenceEnd::Error,
+            other => return Err(anyhow!("Invalid fixed sequence end behavior: {}", other)),
+        };
+
+        Ok(SequenceFixedConfig { keys, end })
+    }
+}
+
+
+impl WindowLimitConfig {
+    /// Creates a new `WindowLimitConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if `WINDOW_LIMIT` is not set, an error if it or `WINDOW_LIMIT_SECS`
+    /// is not a positive number, otherwise a `WindowLimitConfig`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let limit = match var("WINDOW_LIMIT") {
+            Ok(limit) => limit.parse::<u64>().map_err(|_| anyhow!("Invalid window limit value"))?,
+            Err(_) => return Ok(None),
+        };
+
+        let window_secs = var("WINDOW_LIMIT_SECS")
+            .unwrap_or_else(|_| "3600".to_string())
+            .parse::<u64>()
+            .ok()
+            .filter(|secs| *secs > 0)
+            .ok_or_else(|| anyhow!("Invalid window length value"))?;
+
+        Ok(Some(WindowLimitConfig {
+            redis_config: RedisConfig::from_env()?,
+            limit,
+            window_secs,
+        }))
+    }
+}
+
+
+impl UniquenessConfig {
+    /// Creates a new `UniquenessConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if `UNIQUENESS_SERVICE_URL` is not set, an error if one of the other
+    /// variables is invalid, otherwise a `UniquenessConfig`.
+    pub fn from_env() -> Result<Option<Self>> {
+        let url = match var("UNIQUENESS_SERVICE_URL") {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+
+        let fail_open = bool_from_env("UNIQUENESS_FAIL_OPEN", true)?;
+
+        let timeout_ms = var("UNIQUENESS_TIMEOUT_MS")
+            .unwrap_or_else(|_| "100".to_string())
+            .parse::<u64>()
+            .ok()
+            .filter(|timeout| *timeout > 0)
+            .ok_or_else(|| anyhow!("Invalid uniqueness service timeout value"))?;
+
+        let max_rerolls = var("UNIQUENESS_MAX_REROLLS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid uniqueness max rerolls value"))?;
+
+        Ok(Some(UniquenessConfig { url, fail_open, timeout_ms, max_rerolls }))
+    }
+}
+
+
+impl NanoidConfig {
+    /// Creates a new `NanoidConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `NANOID_LENGTH` is not a positive number or `NANOID_ALPHABET`
+    /// is invalid, otherwise a `NanoidConfig`.
+    pub fn from_env() -> Result<Self> {
+        let length = var("NANOID_LENGTH")
+            .unwrap_or_else(|_| "21".to_string())
+            .parse::<usize>()
+            .ok()
+            .filter(|length| *length > 0)
+            .ok_or_else(|| anyhow!("Invalid nanoid length value"))?;
+
+        let alphabet = match var("NANOID_ALPHABET") {
+            Ok(alphabet) => parse_nanoid_alphabet(&alphabet)?,
+            Err(_) => NanoidConfig::default().alphabet,
+        };
+
+        Ok(NanoidConfig { length, alphabet })
+    }
+}
+
+
+impl UuidConfig {
+    /// Creates a new `UuidConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `UUID_VERSION` is neither `v4` nor `v7`, otherwise a `UuidConfig`.
+    pub fn from_env() -> Result<Self> {
+        let version = match var("UUID_VERSION").unwrap_or_else(|_| "v4".to_string()).as_str() {
+            "v4" => UuidVersion::V4,
+            "v7" => UuidVersion::V7,
+            other => return Err(anyhow!("Unsupported UUID version: {}", other)),
+        };
+
+        Ok(UuidConfig { version })
+    }
+}
+
+
+impl SnowflakeConfig {
+    /// Creates a new `SnowflakeConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `SNOWFLAKE_WORKER_ID` is not a number from 0 to 1023,
+    /// otherwise a `SnowflakeConfig`.
+    pub fn from_env() -> Result<Self> {
+        let worker_id = var("SNOWFLAKE_WORKER_ID")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse::<u64>()
+            .ok()
+            .filter(|worker_id| *worker_id <= MAX_WORKER_ID)
+            .ok_or_else(|| anyhow!("Invalid Snowflake worker id value"))?;
+
+        Ok(SnowflakeConfig { worker_id })
+    }
+}
+
+
+impl LedgerConfig {
+    /// Creates a new `LedgerConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if `LEDGER_ENABLED` is not `true`, an error if `LEDGER_TIMEZONE`
+    /// is not a valid IANA timezone, otherwise a `LedgerConfig`.
+    pub fn from_env() -> Result<Option<Self>> {
+        if !bool_from_env("LEDGER_ENABLED", false)? {
+            return Ok(None);
+        }
+
+        let timezone = var("LEDGER_TIMEZONE")
+            .unwrap_or_else(|_| "UTC".to_string())
+            .parse::<Tz>()
+            .map_err(|_| anyhow!("Invalid ledger timezone value"))?;
+
+        Ok(Some(LedgerConfig {
+            redis_config: RedisConfig::from_env()?,
+            timezone,
+        }))
+    }
+}
+
+
+impl KeyFilterConfig {
+    /// Creates a new `KeyFilterConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns `None` if no rejection rule is set, an error if `KEY_FILTER_MAX_REROLLS`
+    /// is not a valid number, otherwise a `KeyFilterConfig`. The regex and the script
+    /// are compiled, and checked, when the filter is built.
+    pub fn from_env() -> Result<Option<Self>> {
+        let reject_regex = var("KEY_REJECT_REGEX").ok();
+
+        let deny_prefixes: Vec<String> = var("KEY_DENY_PREFIXES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|prefix| !prefix.is_empty())
+            .map(String::from)
+            .collect();
+
+        let script_path = var("KEY_SCRIPT_FILE").ok();
+
+        if reject_regex.is_none() && deny_prefixes.is_empty() && script_path.is_none() {
+            return Ok(None);
+        }
+
+        let max_rerolls = var("KEY_FILTER_MAX_REROLLS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse::<usize>()
+            .map_err(|_| anyhow!("Invalid key filter max re-rolls value"))?;
+
+        let script_max_operations = var("KEY_SCRIPT_MAX_OPERATIONS")
+            .unwrap_or_else(|_| "100000".to_string())
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Invalid key script max operations value"))?;
+
+        let script_timeout_ms = var("KEY_SCRIPT_TIMEOUT_MS")
+            .unwrap_or_else(|_| "50".to_string())
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Invalid key script timeout value"))?;
+
+        Ok(Some(KeyFilterConfig {
+            reject_regex,
+            deny_prefixes,
+            script_path,
+            script_max_operations,
+            script_timeout_ms,
+            max_rerolls,
+        }))
+    }
+}
+
+
+impl MetricsConfig {
+    /// Creates a new `MetricsConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `METRICS_BACKEND` is not a supported backend or if the
+    /// backend variables contain invalid values, otherwise a `MetricsConfig`.
+    pub fn from_env() -> Result<Self> {
+        let backend = var("METRICS_BACKEND").unwrap_or_else(|_| "none".to_string());
+        match backend.as_str() {
+            "none" => Ok(MetricsConfig::None),
+            "statsd" => Ok(MetricsConfig::Statsd(StatsdConfig::from_env()?)),
+            "prometheus" => Ok(MetricsConfig::Prometheus(PrometheusConfig::from_env()?)),
+            _ => Err(anyhow!("Unsupported metrics backend: {}", backend)),
+        }
+    }
+}
+
+
+impl StatsdConfig {
+    /// Creates a new `StatsdConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `STATSD_PORT` is not a valid port, otherwise a `StatsdConfig`.
+    pub fn from_env() -> Result<Self> {
+        let host = var("STATSD_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+
+        let port = var("STATSD_PORT")
+            .unwrap_or_else(|_| "8125".to_string())
+            .parse::<u16>()
+            .map_err(|_| anyhow!("Invalid StatsD port value"))?;
+
+        let prefix = var("METRICS_PREFIX").unwrap_or_else(|_| "key_generation_service".to_string());
+
+        Ok(StatsdConfig {
+            host,
+            port,
+            prefix,
+        })
+    }
+}
+
+
+impl PrometheusConfig {
+    /// Creates a new `PrometheusConfig` from environment variables.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `METRICS_PORT` is not a valid port, otherwise a `PrometheusConfig`.
+    pub fn from_env() -> Result<Self> {
+        let port = var("METRICS_PORT")
+            .unwrap_or_else(|_| "9090".to_string())
+            .parse::<u16>()
+            .map_err(|_| anyhow!("Invalid metrics port value"))?;
+
+        Ok(PrometheusConfig { port })
+    }
+}
+
+
+impl ServiceConfig {
+    /// Creates a new `ServiceConfig` from environment variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `generator_type` - The `GENERATOR_TYPE` of the active generator.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `RESPONSE_METADATA` is not a valid boolean, otherwise a `ServiceConfig`.
+    pub fn from_env(generator_type: &'static str) -> Result<Self> {
+        let response_metadata = bool_from_env("RESPONSE_METADATA", false)?;
+
+        let instance_id = var("INSTANCE_ID")
+            .or_else(|_| var("HOSTNAME"))
+            .unwrap_or_else(|_| "unknown".to_string());
+
+        Ok(ServiceConfig {
+            response_metadata,
+            instance_id,
+            generator_type,
         })
     }
 }
@@ -115,21 +988,168 @@ impl GeneratorConfig {
     /// Returns an error if the required environment variables are not set
     /// or if they contain invalid values, otherwise a `GENERATOR_TYPE`.
     pub fn from_env() -> Result<Self> {
-        let generator_type = env::var("GENERATOR_TYPE").unwrap_or_else(|_| "random".to_string());
-        match generator_type.as_str() {
-            "random" => Ok(GeneratorConfig::Random),
+        let generator_type = var("GENERATOR_TYPE").unwrap_or_else(|_| "random".to_string());
+        Self::from_type(&generator_type)
+    }
+
+    /// Creates a new `GeneratorConfig` of the given type, reading its parameters from
+    /// environment variables.
+    ///
+    /// # Arguments
+    ///
+    /// * `generator_type` - A `GENERATOR_TYPE` value.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the type is not supported or its parameters are invalid,
+    /// otherwise a `GeneratorConfig`.
+    pub fn from_type(generator_type: &str) -> Result<Self> {
+        match generator_type {
+            "random" => Ok(GeneratorConfig::Random(RandomConfig::from_env()?)),
             "redis" => Ok(GeneratorConfig::Redis(RedisConfig::from_env()?)),
             "primitive_root_redis" => Ok(GeneratorConfig::PrimitiveRootRedis(
                 RedisConfig::from_env()?,
                 PrimitiveConfig::from_env()?,
             )),
+            "standby_redis" => Ok(GeneratorConfig::StandbyRedis(
+                RedisConfig::from_env()?,
+                StandbyConfig::from_env()?,
+            )),
+            "maintenance_pool" => Ok(GeneratorConfig::MaintenancePool(MaintenancePoolConfig::from_env()?)),
+            "sequence_fixed" => Ok(GeneratorConfig::SequenceFixed(SequenceFixedConfig::from_env()?)),
+            "uuid" => Ok(GeneratorConfig::Uuid(UuidConfig::from_env()?)),
+            "snowflake" => Ok(GeneratorConfig::Snowflake(SnowflakeConfig::from_env()?)),
+            "ulid" => Ok(GeneratorConfig::Ulid),
+            "buffered_redis" => Ok(GeneratorConfig::BufferedRedis(
+                RedisConfig::from_env()?,
+                BufferConfig::from_env()?,
+            )),
+            "nanoid" => Ok(GeneratorConfig::Nanoid(NanoidConfig::from_env()?)),
             _ => Err(anyhow!("Unsupported generator type: {}", generator_type)),
         }
     }
+
+    /// Returns the `GENERATOR_TYPE` value selecting this generator.
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeneratorConfig::Random(_) => "random",
+            GeneratorConfig::Redis(_) => "redis",
+            GeneratorConfig::PrimitiveRootRedis(_, _) => "primitive_root_redis",
+            GeneratorConfig::StandbyRedis(_, _) => "standby_redis",
+            GeneratorConfig::MaintenancePool(_) => "maintenance_pool",
+            GeneratorConfig::SequenceFixed(_) => "sequence_fixed",
+            GeneratorConfig::Uuid(_) => "uuid",
+            GeneratorConfig::Snowflake(_) => "snowflake",
+            GeneratorConfig::Ulid => "ulid",
+            GeneratorConfig::BufferedRedis(_, _) => "buffered_redis",
+            GeneratorConfig::Nanoid(_) => "nanoid",
+        }
+    }
+
+    /// Returns whether the generator emits keys that sort lexicographically in issuance order.
+    /// Counter generators do, since keys are zero-padded base 62 of an increasing counter,
+    /// and so do version 7 UUIDs and ULIDs, which start with a timestamp.
+    pub fn is_time_ordered(&self) -> bool {
+        match self {
+            GeneratorConfig::Redis(_)
+            | GeneratorConfig::StandbyRedis(_, _)
+            | GeneratorConfig::Snowflake(_)
+            | GeneratorConfig::Ulid => true,
+            GeneratorConfig::Uuid(uuid_config) => uuid_config.version == UuidVersion::V7,
+            GeneratorConfig::Random(_)
+            | GeneratorConfig::PrimitiveRootRedis(_, _)
+            | GeneratorConfig::MaintenancePool(_)
+            | GeneratorConfig::SequenceFixed(_)
+            | GeneratorConfig::BufferedRedis(_, _)
+            | GeneratorConfig::Nanoid(_) => false,
+        }
+    }
+
+    /// Returns the leading character identifying the generator family under `SCHEME_PREFIX`:
+    /// `C` for counters, `R` for random, `P` for primitive root and `S` for Snowflake keys.
+    ///
+    /// # Returns
+    ///
+    /// The prefix, or `None` if the generator has no scheme prefix.
+    pub fn scheme_prefix(&self) -> Option<char> {
+        match self {
+            GeneratorConfig::Redis(_) | GeneratorConfig::StandbyRedis(_, _) | GeneratorConfig::BufferedRedis(_, _) => Some('C'),
+            GeneratorConfig::Random(_) => Some('R'),
+            GeneratorConfig::PrimitiveRootRedis(_, _) => Some('P'),
+            GeneratorConfig::Snowflake(_) => Some('S'),
+            GeneratorConfig::MaintenancePool(_)
+            | GeneratorConfig::SequenceFixed(_)
+            | GeneratorConfig::Uuid(_)
+            | GeneratorConfig::Ulid
+            | GeneratorConfig::Nanoid(_) => None,
+        }
+    }
+
+    /// Returns how many distinct keys the generator can issue.
+    ///
+    /// # Arguments
+    ///
+    /// * `keyspace` - The number of distinct keys of the configured length.
+    ///
+    /// # Returns
+    ///
+    /// The capacity, or `None` if it is only known once the generator is built or, for
+    /// UUIDs, ULIDs and nanoids, not bounded by the keyspace.
+    pub fn capacity(&self, keyspace: u128) -> Option<u128> {
+        let capacity = match self {
+            GeneratorConfig::Random(_)
+            | GeneratorConfig::Redis(_)
+            | GeneratorConfig::StandbyRedis(_, _)
+            | GeneratorConfig::BufferedRedis(_, _) => keyspace,
+            GeneratorConfig::PrimitiveRootRedis(_, primitive_config) => primitive_config.prime.saturating_sub(1),
+            GeneratorConfig::SequenceFixed(sequence_config) => sequence_config.keys.len() as u128,
+            GeneratorConfig::Snowflake(_) => SNOWFLAKE_ID_SPACE,
+            GeneratorConfig::MaintenancePool(_)
+            | GeneratorConfig::Uuid(_)
+            | GeneratorConfig::Ulid
+            | GeneratorConfig::Nanoid(_) => return None,
+        };
+        Some(capacity.min(keyspace))
+    }
 }
 
 
 impl GenerationKeyServiceConfig {
+    /// Creates a new `GenerationKeyServiceConfig` from the file named by `CONFIG_FILE`
+    /// if it is set, otherwise from environment variables only.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the file cannot be loaded or the configuration is invalid,
+    /// otherwise a `GenerationKeyServiceConfig`.
+    pub fn load() -> Result<Self> {
+        match env::var("CONFIG_FILE") {
+            Ok(path) => Self::from_file(Path::new(&path)),
+            Err(_) => Self::from_env(),
+        }
+    }
+
+    /// Creates a new `GenerationKeyServiceConfig` from a TOML file, with environment
+    /// variables overriding the settings of the file. The settings stay in effect for
+    /// the rest of the process, so only one file can be loaded.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the TOML file.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if the file cannot be read or parsed, a file was already loaded,
+    /// or the configuration is invalid, otherwise a `GenerationKeyServiceConfig`.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Cannot read CONFIG_FILE from {}: {}", path.display(), err))?;
+        FILE_SETTINGS
+            .set(parse_config_file(&contents)?)
+            .map_err(|_| anyhow!("A configuration file was already loaded"))?;
+        Self::from_env()
+    }
+
     /// Creates a new `GenerationKeyServiceConfig` from environment variables.
     ///
     /// # Returns
@@ -137,16 +1157,848 @@ impl GenerationKeyServiceConfig {
     /// Returns an error if the required environment variables are not set
     /// or if they contain invalid values, otherwise a `GenerationKeyServiceConfig`.
     pub fn from_env() -> Result<Self> {
-        let listen_port = env::var("GENERATION_KEY_SERVICE_PORT")
+        let listen_port = var("GENERATION_KEY_SERVICE_PORT")
             .unwrap_or_else(|_| "8080".to_string())
             .parse::<u16>()?;
 
         let generator_config = GeneratorConfig::from_env()?;
 
+        let encoding_name = var("KEY_ENCODING").unwrap_or_else(|_| "base62".to_string());
+        let check_symbol = bool_from_env("KEY_CHECK_SYMBOL", false)?;
+        let encoding = Encoding::from_name(&encoding_name, check_symbol)
+            .ok_or_else(|| anyhow!("Unsupported key encoding: {} (check symbol: {})", encoding_name, check_symbol))?;
+        if let Ok(digits) = var("NUMBER_DIGITS") {
+            parse_number_digits(&digits)?;
+        }
+        check_number_digits(number_digits(), encoding)?;
+
+        let scheme_prefix = bool_from_env("SCHEME_PREFIX", false)?;
+
+        let shutdown_timeout_secs = var("SHUTDOWN_TIMEOUT_SECS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse::<u64>()
+            .map_err(|_| anyhow!("Invalid shutdown timeout value"))?;
+
+        let alphabet = match var("ALPHABET") {
+            Ok(_) if encoding != Encoding::Base62 => return Err(anyhow!("ALPHABET only applies to the base62 encoding")),
+            Ok(alphabet) => Some(parse_alphabet(&alphabet)?),
+            Err(_) => None,
+        };
+
+        let region_tag = match var("REGION_TAG") {
+            Ok(tag) => Some(parse_region_tag(&tag, encoding, alphabet.as_deref())?),
+            Err(_) => None,
+        };
+
+        let telemetry_required = bool_from_env("TELEMETRY_REQUIRED", false)?;
+        let telemetry_endpoint = var("OTEL_EXPORTER_OTLP_ENDPOINT").unwrap_or_else(|_| "http://localhost:4317".to_string());
+
+        let ledger_config = LedgerConfig::from_env()?;
+
+        let strict = bool_from_env("STRICT_CONFIG", false)?;
+
+        let keys_time_ordered = bool_from_env("KEYS_TIME_ORDERED", false)?;
+
+        let service_config = ServiceConfig::from_env(generator_config.name())?;
+
+        let warmup = bool_from_env("GENERATOR_WARMUP", false)?;
+
+        let metrics_config = MetricsConfig::from_env()?;
+
+        let key_filter_config = KeyFilterConfig::from_env()?;
+
+        let extra_listeners = match var("EXTRA_LISTENERS") {
+            Ok(listeners) => parse_listeners(&listeners, listen_port, GeneratorConfig::from_type)?,
+            Err(_) => Vec::new(),
+        };
+
+        let duplicate_detection = if bool_from_env("DEV_DUPLICATE_DETECTION", false)? {
+            let capacity = var("DEV_DUPLICATE_DETECTION_CAPACITY")
+                .unwrap_or_else(|_| "100000".to_string())
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid duplicate detection capacity value"))?;
+            Some(capacity)
+        } else {
+            None
+        };
+
+        let port_auto_increment = bool_from_env("PORT_AUTO_INCREMENT", false)?;
+
+        let enable_reflection = bool_from_env("ENABLE_REFLECTION", cfg!(debug_assertions))?;
+
+        let expected_min_capacity = match var("EXPECTED_MIN_CAPACITY") {
+            Ok(capacity) => Some(capacity.parse::<u128>().map_err(|_| anyhow!("Invalid expected minimum capacity value"))?),
+            Err(_) => None,
+        };
+
+        let window_limit_config = WindowLimitConfig::from_env()?;
+
+        let uniqueness_config = UniquenessConfig::from_env()?;
+
+        let auto_expand = if bool_from_env("AUTO_EXPAND", false)? {
+            let max_expansions = var("AUTO_EXPAND_MAX")
+                .unwrap_or_else(|_| "1".to_string())
+                .parse::<usize>()
+                .map_err(|_| anyhow!("Invalid auto expand max value"))?;
+            check_number_digits(number_digits() + max_expansions, encoding)?;
+            Some(max_expansions)
+        } else {
+            None
+        };
+
         Ok(GenerationKeyServiceConfig {
             listen_port,
             generator_config,
+            region_tag,
+            telemetry_required,
+            telemetry_endpoint,
+            ledger_config,
+            strict,
+            keys_time_ordered,
+            service_config,
+            warmup,
+            metrics_config,
+            key_filter_config,
+            extra_listeners,
+            duplicate_detection,
+            port_auto_increment,
+            expected_min_capacity,
+            window_limit_config,
+            uniqueness_config,
+            auto_expand,
+            encoding,
+            alphabet,
+            scheme_prefix,
+            shutdown_timeout_secs,
+            enable_reflection,
         })
     }
+
+    /// Checks the configuration for values that are valid but likely mistakes,
+    /// logging a warning for each, or failing under strict mode.
+    ///
+    /// # Returns
+    ///
+    /// Returns an error if `KEYS_TIME_ORDERED` is set with a generator that does not
+    /// produce time-ordered keys, with an `ALPHABET` out of ASCII order or with `AUTO_EXPAND`, if a generator capacity is below `EXPECTED_MIN_CAPACITY`,
+    /// or under strict mode if a likely misconfiguration is found.
+    pub fn validate(&self) -> Result<()> {
+        self.validate_with_keyspace(max_number() + 1)
+    }
+
+    /// Validates the configuration like `validate`, for keys of `keyspace` distinct values.
+    fn validate_with_keyspace(&self, keyspace: u128) -> Result<()> {
+        if self.scheme_prefix && self.region_tag.is_some() {
+            return Err(anyhow!("SCHEME_PREFIX cannot be combined with REGION_TAG, both take the leading character"));
+        }
+        // The region tag or scheme prefix fixes the leading character, so each region owns one base-th of the keys.
+        let region_keyspace = if self.region_tag.is_some() || self.scheme_prefix { keyspace / self.encoding.base() } else { keyspace };
+        let extra_generators = self.extra_listeners.iter().map(|listener| &listener.generator_config);
+        for generator_config in std::iter::once(&self.generator_config).chain(extra_generators) {
+            match generator_config.capacity(region_keyspace) {
+                Some(capacity) => {
+                    info!("the {} generator can issue {} keys", generator_config.name(), capacity);
+                    if let Some(expected) = self.expected_min_capacity.filter(|expected| capacity < *expected) {
+                        return Err(anyhow!(
+                            "The {} generator can issue {} keys, below EXPECTED_MIN_CAPACITY {}",
+                            generator_config.name(), capacity, expected
+                        ));
+                    }
+                }
+                None => info!("the capacity of the {} generator cannot be computed from the configuration", generator_config.name()),
+            }
+            if self.keys_time_ordered && !generator_config.is_time_ordered() {
+                return Err(anyhow!(
+                    "KEYS_TIME_ORDERED requires a time-ordered generator, but {} is not",
+                    generator_config.name()
+                ));
+            }
+            // Keys encoded with the key alphabet only sort like their numbers if the alphabet is
+            // in ASCII order and all keys have the same length.
+            if self.keys_time_ordered
+                && matches!(generator_config, GeneratorConfig::Redis(_) | GeneratorConfig::StandbyRedis(_, _) | GeneratorConfig::Snowflake(_))
+            {
+                if self.alphabet.as_ref().is_some_and(|alphabet| !alphabet.as_bytes().is_sorted()) {
+                    return Err(anyhow!("KEYS_TIME_ORDERED requires an ALPHABET in ascending ASCII order"));
+                }
+                if self.auto_expand.is_some() {
+                    return Err(anyhow!("KEYS_TIME_ORDERED cannot be combined with AUTO_EXPAND, expanded keys sort before shorter ones"));
+                }
+            }
+            if matches!(generator_config, GeneratorConfig::Snowflake(_)) && region_keyspace < SNOWFLAKE_ID_SPACE {
+                return Err(anyhow!("Snowflake IDs take 63 bits and do not fit in the keyspace, raise NUMBER_DIGITS"));
+            }
+            if matches!(generator_config, GeneratorConfig::Uuid(_) | GeneratorConfig::Ulid | GeneratorConfig::Nanoid(_))
+                && (self.region_tag.is_some() || self.warmup)
+            {
+                return Err(anyhow!("REGION_TAG and GENERATOR_WARMUP do not apply to the {} generator", generator_config.name()));
+            }
+            if self.scheme_prefix {
+                match generator_config.scheme_prefix() {
+                    Some(prefix) if self.encoding.symbols().contains(prefix) => {}
+                    Some(prefix) => return Err(anyhow!("The scheme prefix {} is not in the key alphabet", prefix)),
+                    None => return Err(anyhow!("SCHEME_PREFIX does not apply to the {} generator", generator_config.name())),
+                }
+            }
+            match generator_config {
+                GeneratorConfig::PrimitiveRootRedis(_, primitive_config) => primitive_config.validate(self.strict)?,
+                GeneratorConfig::Random(random_config) => random_config.validate_keyspace(region_keyspace, self.strict)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+
+
+/// The settings of the file loaded with `from_file`, keyed by environment variable name.
+static FILE_SETTINGS: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+thread_local! {
+    /// The settings replacing `FILE_SETTINGS` on this thread within `with_file_settings`.
+    static SCOPED_SETTINGS: RefCell<Option<HashMap<String, String>>> = const { RefCell::new(None) };
 }
 
+
+/// Reads an environment variable, falling back to the setting of the same name in the
+/// loaded configuration file.
+pub(crate) fn var(name: &str) -> Result<String, env::VarError> {
+    env::var(name).or_else(|err| {
+        SCOPED_SETTINGS
+            .with_borrow(|scoped| scoped.as_ref().map(|settings| settings.get(name).cloned()))
+            .unwrap_or_else(|| FILE_SETTINGS.get().and_then(|settings| settings.get(name).cloned()))
+            .ok_or(err)
+    })
+}
+
+
+/// Runs `f` with `settings` in place of the loaded configuration file, on the current
+/// thread only, so tests can read a configuration file without loading it for the
+/// whole process.
+///
+/// # Arguments
+///
+/// * `settings` - The settings, keyed by environment variable name.
+/// * `f` - The function reading the configuration.
+#[cfg(test)]
+pub(crate) fn with_file_settings<T>(settings: HashMap<String, String>, f: impl FnOnce() -> T) -> T {
+    let previous = SCOPED_SETTINGS.replace(Some(settings));
+    let result = f();
+    SCOPED_SETTINGS.set(previous);
+    result
+}
+
+
+/// Parses a TOML configuration file into settings keyed by environment variable name.
+///
+/// Keys are uppercased and nested tables are joined with underscores, so `url` in a
+/// `[redis]` table is `REDIS_URL`. Arrays are joined with commas, as in `EXTRA_LISTENERS`.
+/// Numbers beyond the TOML integer range, like large primes, must be quoted.
+///
+/// # Returns
+///
+/// Returns an error if the file is not valid TOML, holds a date, or sets a variable
+/// twice, otherwise the settings.
+fn parse_config_file(contents: &str) -> Result<HashMap<String, String>> {
+    let table = contents
+        .parse::<toml::Table>()
+        .map_err(|err| anyhow!("Invalid CONFIG_FILE: {}", err))?;
+    let mut settings = HashMap::new();
+    flatten_settings("", table, &mut settings)?;
+    Ok(settings)
+}
+
+
+/// Adds the values of `table` to `settings`, prefixing their names with `prefix`.
+fn flatten_settings(prefix: &str, table: toml::Table, settings: &mut HashMap<String, String>) -> Result<()> {
+    for (key, value) in table {
+        let name = if prefix.is_empty() { key.to_uppercase() } else { format!("{}_{}", prefix, key.to_uppercase()) };
+        let value = match value {
+            toml::Value::Table(table) => {
+                flatten_settings(&name, table, settings)?;
+                continue;
+            }
+            toml::Value::Array(values) => values.iter().map(setting_value).collect::<Result<Vec<_>>>()?.join(","),
+            value => setting_value(&value)?,
+        };
+        if settings.insert(name.clone(), value).is_some() {
+            return Err(anyhow!("{} is set more than once in CONFIG_FILE", name));
+        }
+    }
+    Ok(())
+}
+
+
+/// Returns the value of a setting as an environment variable would hold it.
+fn setting_value(value: &toml::Value) -> Result<String> {
+    match value {
+        toml::Value::String(value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        value => Err(anyhow!("Unsupported CONFIG_FILE value: {}", value)),
+    }
+}
+
+
+/// Reads a boolean flag from an environment variable.
+///
+/// # Arguments
+///
+/// * `name` - The name of the environment variable.
+/// * `default` - The value used when the variable is not set.
+///
+/// # Returns
+///
+/// Returns an error if the variable is not `true` or `false`, otherwise the flag.
+fn bool_from_env(name: &str, default: bool) -> Result<bool> {
+    match var(name) {
+        Ok(value) => value
+            .parse::<bool>()
+            .map_err(|_| anyhow!("Invalid boolean value for {}: {}", name, value)),
+        Err(_) => Ok(default),
+    }
+}
+
+
+/// Parses the `EXTRA_LISTENERS` list of `port=generator_type` entries, separated by commas.
+///
+/// # Arguments
+///
+/// * `listeners` - The raw `EXTRA_LISTENERS` value.
+/// * `listen_port` - The main port, which no additional listener may reuse.
+/// * `generator_config` - Builds the configuration of a generator type.
+///
+/// # Returns
+///
+/// Returns an error if an entry is malformed, a port is used twice, or a generator
+/// configuration cannot be built, otherwise the listeners.
+fn parse_listeners(
+    listeners: &str,
+    listen_port: u16,
+    generator_config: impl Fn(&str) -> Result<GeneratorConfig>,
+) -> Result<Vec<ListenerConfig>> {
+    let mut ports = vec![listen_port];
+    let mut result = Vec::new();
+    for entry in listeners.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (port, generator_type) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid listener, expected port=generator_type: {}", entry))?;
+        let port = port.trim().parse::<u16>().map_err(|_| anyhow!("Invalid listener port: {}", entry))?;
+        if ports.contains(&port) {
+            return Err(anyhow!("Port {} is used by more than one listener", port));
+        }
+        ports.push(port);
+        result.push(ListenerConfig {
+            port,
+            generator_config: generator_config(generator_type.trim())?,
+        });
+    }
+    Ok(result)
+}
+
+
+/// Reads a secret from the file named by `{name}_FILE`, as mounted by container
+/// orchestrators, or else from the `name` environment variable.
+///
+/// # Arguments
+///
+/// * `name` - The name of the environment variable holding the secret.
+///
+/// # Returns
+///
+/// Returns an error if `{name}_FILE` is set but cannot be read, otherwise the secret,
+/// or `None` if neither variable is set.
+fn secret_from_env(name: &str) -> Result<Option<String>> {
+    read_secret(name, |name| var(name).ok())
+}
+
+
+/// Reads a secret like `secret_from_env`, looking variables up with `lookup`.
+/// Trailing newlines of the file are trimmed.
+fn read_secret(name: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<Option<String>> {
+    let file_var = format!("{name}_FILE");
+    match lookup(&file_var) {
+        Some(path) => {
+            let content = std::fs::read_to_string(&path)
+                .map_err(|err| anyhow!("Cannot read {} from {}: {}", file_var, path, err))?;
+            Ok(Some(content.trim_end_matches(['\n', '\r']).to_string()))
+        }
+        None => Ok(lookup(name)),
+    }
+}
+
+
+/// Parses a `NUMBER_DIGITS` value. The generators fall back to 8 digits on an invalid
+/// value, so it is rejected here rather than silently ignored.
+///
+/// # Returns
+///
+/// Returns an error if the value is not a positive number, otherwise the number of digits.
+fn parse_number_digits(digits: &str) -> Result<usize> {
+    digits
+        .parse::<usize>()
+        .ok()
+        .filter(|digits| *digits > 0)
+        .ok_or_else(|| anyhow!("Invalid NUMBER_DIGITS value: {}", digits))
+}
+
+
+/// Checks that every key of `digits` digits in `encoding` fits in the `u128` arithmetic.
+///
+/// # Returns
+///
+/// Returns an error naming the largest supported `NUMBER_DIGITS` if it does not.
+fn check_number_digits(digits: usize, encoding: Encoding) -> Result<()> {
+    if digits > encoding.max_digits() {
+        return Err(anyhow!(
+            "NUMBER_DIGITS {} is too large for the {:?} encoding, at most {} digits are supported",
+            digits, encoding, encoding.max_digits()
+        ));
+    }
+    Ok(())
+}
+
+
+/// Parses an `ALPHABET` replacing the base 62 alphabet.
+///
+/// # Returns
+///
+/// Returns an error if the alphabet does not hold exactly 62 unique printable ASCII
+/// characters, otherwise the alphabet.
+fn parse_alphabet(alphabet: &str) -> Result<String> {
+    if alphabet.chars().count() != 62 {
+        return Err(anyhow!("ALPHABET must hold exactly 62 characters, got {}", alphabet.chars().count()));
+    }
+    if let Some(c) = alphabet.chars().find(|c| !c.is_ascii_graphic()) {
+        return Err(anyhow!("ALPHABET must only hold printable ASCII characters, got {:?}", c));
+    }
+    let mut seen = HashSet::new();
+    if let Some(c) = alphabet.chars().find(|c| !seen.insert(*c)) {
+        return Err(anyhow!("ALPHABET holds {:?} more than once", c));
+    }
+    Ok(alphabet.to_string())
+}
+
+
+/// Parses a `NANOID_ALPHABET`.
+///
+/// # Returns
+///
+/// Returns an error if the alphabet holds fewer than 2 or more than 255 characters, or
+/// a character more than once, otherwise its characters.
+fn parse_nanoid_alphabet(alphabet: &str) -> Result<Vec<char>> {
+    let symbols: Vec<char> = alphabet.chars().collect();
+    if !(2..=255).contains(&symbols.len()) {
+        return Err(anyhow!("NANOID_ALPHABET must hold 2 to 255 characters, got {}", symbols.len()));
+    }
+    let mut seen = HashSet::new();
+    if let Some(c) = symbols.iter().find(|c| !seen.insert(**c)) {
+        return Err(anyhow!("NANOID_ALPHABET holds {:?} more than once", c));
+    }
+    Ok(symbols)
+}
+
+
+/// Parses a region tag, which must be a single digit symbol of the key encoding.
+///
+/// # Arguments
+///
+/// * `tag` - The raw `REGION_TAG` value.
+/// * `encoding` - The key encoding.
+///
+/// # Returns
+///
+/// Returns an error if the tag is not exactly one digit symbol, or if keys carry a
+/// check symbol that the tag would invalidate, otherwise the tag.
+fn parse_region_tag(tag: &str, encoding: Encoding, alphabet: Option<&str>) -> Result<char> {
+    if encoding.has_check_symbol() {
+        return Err(anyhow!("REGION_TAG cannot be combined with KEY_CHECK_SYMBOL"));
+    }
+    let symbols = alphabet.unwrap_or(encoding.symbols());
+    let mut chars = tag.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if symbols.contains(c) => Ok(c),
+        _ => Err(anyhow!("Invalid region tag: {}", tag)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::ALPHABET;
+
+    fn primitive_config(start: u128) -> PrimitiveConfig {
+        PrimitiveConfig {
+            prime: 1000003,
+            start,
+            primitive_root: 2,
+        }
+    }
+
+    #[test]
+    fn test_start_lower_than_prime_is_valid() {
+        assert!(primitive_config(0).validate(true).is_ok());
+        assert!(primitive_config(1000002).validate(true).is_ok());
+    }
+
+    #[test]
+    fn test_start_not_lower_than_prime() {
+        assert!(primitive_config(1000003).validate(false).is_ok());
+        let err = primitive_config(1000005).validate(true).unwrap_err();
+        assert_eq!(err.to_string(), "Increment start 1000005 is not lower than prime 1000003, it wraps to 2");
+    }
+
+    #[test]
+    fn test_small_random_keyspace() {
+        let config = RandomConfig { min_keyspace: 1_000_000, ..RandomConfig::default() };
+        assert!(config.validate_keyspace(62_u128.pow(4), true).is_ok());
+        assert!(config.validate_keyspace(62_u128.pow(3), false).is_ok());
+        let err = config.validate_keyspace(62_u128.pow(3), true).unwrap_err();
+        assert!(err.to_string().starts_with("Random keyspace 238328 is below the safety threshold 1000000"));
+    }
+
+    #[test]
+    fn test_region_tag_shrinks_random_keyspace() {
+        let random = GeneratorConfig::Random(RandomConfig { min_keyspace: 1_000_000, ..RandomConfig::default() });
+        let config = GenerationKeyServiceConfig { strict: true, ..service_config(random, false) };
+        assert!(config.validate_with_keyspace(62_u128.pow(4)).is_ok());
+        let err = GenerationKeyServiceConfig { region_tag: Some('E'), ..config }
+            .validate_with_keyspace(62_u128.pow(4))
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Random keyspace 238328 is below the safety threshold 1000000"));
+    }
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: Vec<(String, String)> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |name| vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone())
+    }
+
+    #[test]
+    fn test_secret_from_file_takes_precedence() {
+        let path = env::temp_dir().join(format!("redis-url-secret-{}", std::process::id()));
+        std::fs::write(&path, "redis://secret:6379\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let secret = read_secret("REDIS_URL", lookup(&[("REDIS_URL", "redis://plain:6379"), ("REDIS_URL_FILE", &path)]));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(secret.unwrap(), Some("redis://secret:6379".to_string()));
+    }
+
+    #[test]
+    fn test_secret_from_env() {
+        let secret = read_secret("REDIS_URL", lookup(&[("REDIS_URL", "redis://plain:6379")])).unwrap();
+        assert_eq!(secret, Some("redis://plain:6379".to_string()));
+        assert_eq!(read_secret("REDIS_URL", lookup(&[])).unwrap(), None);
+    }
+
+    #[test]
+    fn test_unreadable_secret_file() {
+        let err = read_secret("REDIS_URL", lookup(&[("REDIS_URL_FILE", "/nonexistent/redis-url")])).unwrap_err();
+        assert!(err.to_string().starts_with("Cannot read REDIS_URL_FILE from /nonexistent/redis-url"));
+    }
+
+    const SAMPLE_CONFIG_FILE: &str = r#"
+generation_key_service_port = 8080
+extra_listeners = ["9090=uuid", "9091=ulid"]
+
+[generator]
+type = "primitive_root_redis"
+prime = 1000003
+prime_primitive = 2
+increment_start = 5
+
+[redis]
+url = "redis://localhost:6379"
+pool_size = 16
+"#;
+
+    #[test]
+    fn test_parse_config_file() {
+        let settings = parse_config_file(SAMPLE_CONFIG_FILE).unwrap();
+        let expected: HashMap<String, String> = [
+            ("GENERATION_KEY_SERVICE_PORT", "8080"),
+            ("EXTRA_LISTENERS", "9090=uuid,9091=ulid"),
+            ("GENERATOR_TYPE", "primitive_root_redis"),
+            ("GENERATOR_PRIME", "1000003"),
+            ("GENERATOR_PRIME_PRIMITIVE", "2"),
+            ("GENERATOR_INCREMENT_START", "5"),
+            ("REDIS_URL", "redis://localhost:6379"),
+            ("REDIS_POOL_SIZE", "16"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        assert_eq!(settings, expected);
+    }
+
+    #[test]
+    fn test_generator_from_config_file() {
+        let settings = parse_config_file(SAMPLE_CONFIG_FILE).unwrap();
+        let expected = GeneratorConfig::PrimitiveRootRedis(
+            RedisConfig { retry: RetryConfig { max_attempts: 3, base_delay_ms: 50 }, ..redis_config() },
+            PrimitiveConfig { prime: 1000003, start: 5, primitive_root: 2 },
+        );
+        assert_eq!(with_file_settings(settings, GeneratorConfig::from_env).unwrap(), expected);
+        assert!(var("GENERATOR_PRIME").is_err());
+    }
+
+    #[test]
+    fn test_invalid_config_files() {
+        let err = parse_config_file("redis_url = \"a\"\n[redis]\nurl = \"b\"\n").unwrap_err();
+        assert_eq!(err.to_string(), "REDIS_URL is set more than once in CONFIG_FILE");
+        assert!(parse_config_file("started = 2024-01-01").is_err());
+        assert!(parse_config_file("prime =").is_err());
+    }
+
+    fn redis_config() -> RedisConfig {
+        RedisConfig {
+            url: "redis://localhost:6379".to_string(),
+            monotonic_guard: false,
+            pool_size: 16,
+            loss_threshold: None,
+            high_water_file: None,
+            retry: RetryConfig::default(),
+            coalesce: None,
+        }
+    }
+
+    fn service_config(generator_config: GeneratorConfig, keys_time_ordered: bool) -> GenerationKeyServiceConfig {
+        GenerationKeyServiceConfig {
+            listen_port: 8080,
+            generator_config,
+            region_tag: None,
+            telemetry_required: false,
+            telemetry_endpoint: "http://localhost:4317".to_string(),
+            ledger_config: None,
+            strict: false,
+            keys_time_ordered,
+            service_config: ServiceConfig::default(),
+            warmup: false,
+            metrics_config: MetricsConfig::None,
+            key_filter_config: None,
+            extra_listeners: Vec::new(),
+            duplicate_detection: None,
+            port_auto_increment: false,
+            expected_min_capacity: None,
+            window_limit_config: None,
+            uniqueness_config: None,
+            auto_expand: None,
+            encoding: Encoding::Base62,
+            alphabet: None,
+            scheme_prefix: false,
+            shutdown_timeout_secs: 30,
+            enable_reflection: false,
+        }
+    }
+
+    #[test]
+    fn test_time_ordered_rejects_incompatible_generator() {
+        let err = service_config(GeneratorConfig::Random(RandomConfig::default()), true).validate().unwrap_err();
+        assert_eq!(err.to_string(), "KEYS_TIME_ORDERED requires a time-ordered generator, but random is not");
+        let redis = redis_config();
+        let primitive = GeneratorConfig::PrimitiveRootRedis(redis.clone(), primitive_config(0));
+        assert!(service_config(primitive, true).validate().is_err());
+    }
+
+    #[test]
+    fn test_time_ordered_accepts_counter_generator() {
+        let redis = redis_config();
+        assert!(service_config(GeneratorConfig::Redis(redis), true).validate().is_ok());
+        assert!(service_config(GeneratorConfig::Random(RandomConfig::default()), false).validate().is_ok());
+    }
+
+    #[test]
+    fn test_time_ordered_rejects_unsorted_alphabet() {
+        let redis = GeneratorConfig::Redis(redis_config());
+        let sorted = ALPHABET.to_string();
+        let config = GenerationKeyServiceConfig { alphabet: Some(sorted.clone()), ..service_config(redis.clone(), true) };
+        assert!(config.validate().is_ok());
+        let shuffled: String = sorted.chars().rev().collect();
+        let config = GenerationKeyServiceConfig { alphabet: Some(shuffled), ..service_config(redis, true) };
+        assert_eq!(config.validate().unwrap_err().to_string(), "KEYS_TIME_ORDERED requires an ALPHABET in ascending ASCII order");
+    }
+
+    #[test]
+    fn test_time_ordered_rejects_auto_expand() {
+        let redis = GeneratorConfig::Redis(redis_config());
+        assert!(GenerationKeyServiceConfig { auto_expand: Some(1), ..service_config(redis.clone(), false) }.validate().is_ok());
+        let err = GenerationKeyServiceConfig { auto_expand: Some(1), ..service_config(redis, true) }.validate().unwrap_err();
+        assert_eq!(err.to_string(), "KEYS_TIME_ORDERED cannot be combined with AUTO_EXPAND, expanded keys sort before shorter ones");
+    }
+
+    fn redis_only(generator_type: &str) -> Result<GeneratorConfig> {
+        match generator_type {
+            "redis" => Ok(GeneratorConfig::Redis(redis_config())),
+            "random" => Ok(GeneratorConfig::Random(RandomConfig::default())),
+            _ => Err(anyhow!("Unsupported generator type: {}", generator_type)),
+        }
+    }
+
+    #[test]
+    fn test_parse_listeners() {
+        let listeners = parse_listeners("9090=redis, 9091 = random", 8080, redis_only).unwrap();
+        assert_eq!(listeners, vec![
+            ListenerConfig { port: 9090, generator_config: GeneratorConfig::Redis(redis_config()) },
+            ListenerConfig { port: 9091, generator_config: GeneratorConfig::Random(RandomConfig::default()) },
+        ]);
+        assert!(parse_listeners("", 8080, redis_only).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_listeners_rejects_invalid_entries() {
+        assert!(parse_listeners("9090", 8080, redis_only).is_err());
+        assert!(parse_listeners("port=redis", 8080, redis_only).is_err());
+        assert!(parse_listeners("9090=unknown", 8080, redis_only).is_err());
+        let err = parse_listeners("8080=redis", 8080, redis_only).unwrap_err();
+        assert_eq!(err.to_string(), "Port 8080 is used by more than one listener");
+        assert!(parse_listeners("9090=redis,9090=random", 8080, redis_only).is_err());
+    }
+
+    #[test]
+    fn test_under_provisioned_capacity_fails() {
+        let primitive = GeneratorConfig::PrimitiveRootRedis(redis_config(), primitive_config(0));
+        let mut config = service_config(primitive, false);
+        config.expected_min_capacity = Some(2_000_000);
+        let err = config.validate_with_keyspace(62_u128.pow(8)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The primitive_root_redis generator can issue 1000002 keys, below EXPECTED_MIN_CAPACITY 2000000"
+        );
+        let mut config = service_config(GeneratorConfig::Redis(redis_config()), false);
+        config.expected_min_capacity = Some(62_u128.pow(4));
+        assert!(config.validate_with_keyspace(62_u128.pow(3)).is_err());
+    }
+
+    #[test]
+    fn test_sufficient_capacity_passes() {
+        let primitive = GeneratorConfig::PrimitiveRootRedis(redis_config(), primitive_config(0));
+        let mut config = service_config(primitive, false);
+        config.expected_min_capacity = Some(1_000_000);
+        assert!(config.validate_with_keyspace(62_u128.pow(8)).is_ok());
+        config.region_tag = Some('E');
+        assert!(config.validate_with_keyspace(62_u128.pow(4)).is_err());
+    }
+
+    #[test]
+    fn test_scheme_prefixes() {
+        assert_eq!(GeneratorConfig::Redis(redis_config()).scheme_prefix(), Some('C'));
+        assert_eq!(GeneratorConfig::Random(RandomConfig::default()).scheme_prefix(), Some('R'));
+        assert_eq!(GeneratorConfig::PrimitiveRootRedis(redis_config(), primitive_config(0)).scheme_prefix(), Some('P'));
+        assert_eq!(GeneratorConfig::Snowflake(SnowflakeConfig { worker_id: 1 }).scheme_prefix(), Some('S'));
+        assert_eq!(GeneratorConfig::Ulid.scheme_prefix(), None);
+    }
+
+    #[test]
+    fn test_scheme_prefix_takes_a_digit() {
+        let mut config = service_config(GeneratorConfig::Redis(redis_config()), false);
+        config.scheme_prefix = true;
+        config.expected_min_capacity = Some(62_u128.pow(7));
+        assert!(config.validate_with_keyspace(62_u128.pow(8)).is_ok());
+        config.expected_min_capacity = Some(62_u128.pow(8));
+        assert!(config.validate_with_keyspace(62_u128.pow(8)).is_err());
+        config.expected_min_capacity = None;
+        config.region_tag = Some('E');
+        assert!(config.validate_with_keyspace(62_u128.pow(8)).is_err());
+        let ulid = GenerationKeyServiceConfig { scheme_prefix: true, ..service_config(GeneratorConfig::Ulid, false) };
+        let err = ulid.validate_with_keyspace(62_u128.pow(8)).unwrap_err();
+        assert_eq!(err.to_string(), "SCHEME_PREFIX does not apply to the ulid generator");
+    }
+
+    #[test]
+    fn test_time_ordered_checks_extra_listeners() {
+        let mut config = service_config(GeneratorConfig::Redis(redis_config()), true);
+        config.extra_listeners = parse_listeners("9090=random", 8080, redis_only).unwrap();
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.to_string(), "KEYS_TIME_ORDERED requires a time-ordered generator, but random is not");
+    }
+
+    #[test]
+    fn test_uuid_rejects_region_tag() {
+        let uuid = GeneratorConfig::Uuid(UuidConfig { version: UuidVersion::V7 });
+        assert!(uuid.is_time_ordered());
+        assert!(service_config(uuid.clone(), true).validate_with_keyspace(62_u128.pow(8)).is_ok());
+        let config = GenerationKeyServiceConfig { region_tag: Some('E'), ..service_config(uuid, false) };
+        assert!(config.validate_with_keyspace(62_u128.pow(8)).is_err());
+    }
+
+    #[test]
+    fn test_ulid_rejects_warmup() {
+        assert!(GeneratorConfig::Ulid.is_time_ordered());
+        let config = GenerationKeyServiceConfig { warmup: true, ..service_config(GeneratorConfig::Ulid, true) };
+        let err = config.validate_with_keyspace(62_u128.pow(8)).unwrap_err();
+        assert_eq!(err.to_string(), "REGION_TAG and GENERATOR_WARMUP do not apply to the ulid generator");
+    }
+
+    #[test]
+    fn test_parse_nanoid_alphabet() {
+        assert_eq!(parse_nanoid_alphabet("abc").unwrap(), vec!['a', 'b', 'c']);
+        assert_eq!(parse_nanoid_alphabet("a").unwrap_err().to_string(), "NANOID_ALPHABET must hold 2 to 255 characters, got 1");
+        assert_eq!(parse_nanoid_alphabet("abca").unwrap_err().to_string(), "NANOID_ALPHABET holds 'a' more than once");
+        let nanoid = GeneratorConfig::Nanoid(NanoidConfig::default());
+        assert_eq!(nanoid.capacity(62_u128.pow(8)), None);
+        let err = GenerationKeyServiceConfig { warmup: true, ..service_config(nanoid, false) }
+            .validate_with_keyspace(62_u128.pow(8))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "REGION_TAG and GENERATOR_WARMUP do not apply to the nanoid generator");
+    }
+
+    #[test]
+    fn test_snowflake_needs_63_bit_keyspace() {
+        let snowflake = GeneratorConfig::Snowflake(SnowflakeConfig { worker_id: 1 });
+        assert!(service_config(snowflake.clone(), true).validate_with_keyspace(62_u128.pow(10)).is_err());
+        assert!(service_config(snowflake, true).validate_with_keyspace(62_u128.pow(11)).is_ok());
+    }
+
+    #[test]
+    fn test_number_digits_fit() {
+        assert!(check_number_digits(24, Encoding::Base36).is_ok());
+        let err = check_number_digits(25, Encoding::Base36).unwrap_err();
+        assert_eq!(err.to_string(), "NUMBER_DIGITS 25 is too large for the Base36 encoding, at most 24 digits are supported");
+        assert!(check_number_digits(10, Encoding::Base62).is_ok());
+        assert!(check_number_digits(11, Encoding::Base62).is_ok());
+        assert!(check_number_digits(21, Encoding::Base62).is_ok());
+        let err = check_number_digits(22, Encoding::Base62).unwrap_err();
+        assert_eq!(err.to_string(), "NUMBER_DIGITS 22 is too large for the Base62 encoding, at most 21 digits are supported");
+    }
+
+    #[test]
+    fn test_parse_number_digits() {
+        assert_eq!(parse_number_digits("11").unwrap(), 11);
+        assert_eq!(parse_number_digits("0").unwrap_err().to_string(), "Invalid NUMBER_DIGITS value: 0");
+        assert!(parse_number_digits("eight").is_err());
+        assert!(parse_number_digits("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_region_tag_crockford() {
+        assert_eq!(parse_region_tag("E", Encoding::Crockford32 { check_symbol: false }, None).unwrap(), 'E');
+        assert!(parse_region_tag("U", Encoding::Crockford32 { check_symbol: false }, None).is_err());
+        assert!(parse_region_tag("e", Encoding::Crockford32 { check_symbol: false }, None).is_err());
+        assert!(parse_region_tag("E", Encoding::Crockford32 { check_symbol: true }, None).is_err());
+    }
+
+    #[test]
+    fn test_parse_alphabet() {
+        let reversed: String = ALPHABET.chars().rev().collect();
+        assert_eq!(parse_alphabet(&reversed).unwrap(), reversed);
+        let err = parse_alphabet(&ALPHABET[1..]).unwrap_err();
+        assert_eq!(err.to_string(), "ALPHABET must hold exactly 62 characters, got 61");
+        let err = parse_alphabet(&format!("{}0", &ALPHABET[..61])).unwrap_err();
+        assert_eq!(err.to_string(), "ALPHABET holds '0' more than once");
+        let err = parse_alphabet(&format!(" {}", &ALPHABET[1..])).unwrap_err();
+        assert_eq!(err.to_string(), "ALPHABET must only hold printable ASCII characters, got ' '");
+        let dashed = format!("-{}", &ALPHABET[1..]);
+        assert_eq!(parse_region_tag("-", Encoding::Base62, Some(dashed.as_str())).unwrap(), '-');
+    }
+
+    #[test]
+    fn test_parse_region_tag() {
+        assert_eq!(parse_region_tag("E", Encoding::Base62, None).unwrap(), 'E');
+        assert_eq!(parse_region_tag("z", Encoding::Base62, None).unwrap(), 'z');
+        assert!(parse_region_tag("", Encoding::Base62, None).is_err());
+        assert!(parse_region_tag("EU", Encoding::Base62, None).is_err());
+        assert!(parse_region_tag("-", Encoding::Base62, None).is_err());
+    }
+}
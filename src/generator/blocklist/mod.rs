@@ -0,0 +1,62 @@
+//! This module defines the blocklist of integer values that reusable generators
+//! (those drawing values at random) must never emit.
+//!
+//! Counter-based generators emit each value once, so a blocked value can only be
+//! reissued by generators that may draw it again; they are the only users.
+use std::fmt::Debug;
+use deadpool_redis::Pool;
+use tonic::async_trait;
+use crate::config::RedisConfig;
+use crate::generator::error::GeneratorError;
+use crate::generator::redis::{map_redis_error, pooled_connection, redis_pool};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// The Redis set holding the blocked values.
+pub const BLOCKLIST_KEY: &str = "blocked:values";
+
+
+/// A trait for sets of integer values that must never be emitted.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait Blocklist: Debug + Send + Sync {
+    /// Checks whether `value` is blocked.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `true` if the value is blocked, or a `GeneratorError`.
+    async fn contains(&self, value: u128) -> Result<bool, GeneratorError>;
+}
+
+
+/// A `Blocklist` stored in the Redis set `blocked:values`.
+#[derive(Debug)]
+pub struct RedisBlocklist {
+    pool: Pool,
+}
+
+
+impl RedisBlocklist {
+    /// Creates a new `RedisBlocklist`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Redis configuration.
+    pub fn new(config: &RedisConfig) -> Result<Self, GeneratorError> {
+        Ok(Self { pool: redis_pool(config)? })
+    }
+}
+
+
+#[async_trait]
+impl Blocklist for RedisBlocklist {
+    async fn contains(&self, value: u128) -> Result<bool, GeneratorError> {
+        redis::cmd("SISMEMBER")
+            .arg(BLOCKLIST_KEY)
+            .arg(value.to_string())
+            .query_async(&mut pooled_connection(&self.pool).await?)
+            .await
+            .map_err(map_redis_error)
+    }
+}
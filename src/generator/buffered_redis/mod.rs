@@ -0,0 +1,234 @@
+//! This module defines a Redis-based key generator that reserves blocks of counter
+//! values with a single `INCRBY` and hands them out from memory.
+//!
+//! Every block is reserved atomically in Redis, so keys stay unique across instances and
+//! restarts. The values left in the buffer when the process stops are never issued, which
+//! leaves gaps in the sequence, and keys are only ordered within one instance.
+use std::collections::VecDeque;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tonic::async_trait;
+use tracing::warn;
+use crate::config::{BufferConfig, RedisConfig};
+use crate::generator::GeneratorInteger;
+use crate::generator::error::GeneratorError;
+use crate::generator::redis::RedisGenerator;
+
+
+/// A key generator handing out counter values reserved from Redis in blocks.
+///
+/// Once fewer than `refill_below` values are left, the next block is reserved in a
+/// background task, so draws only wait for Redis when the buffer runs empty.
+#[derive(Debug)]
+pub struct BufferedRedisGenerator {
+    redis_generator: Arc<RedisGenerator>,
+    buffer: Arc<Mutex<VecDeque<u128>>>,
+    /// Held while a block is reserved, so at most one refill is in flight and blocks are
+    /// appended in the order they were reserved.
+    refill: Arc<Mutex<()>>,
+    buffer_config: BufferConfig,
+}
+
+
+impl BufferedRedisGenerator {
+    /// Creates a new `BufferedRedisGenerator`. The first block is reserved on first use.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Redis configuration.
+    /// * `buffer_config` - The block size and refill threshold.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the generator, or a `GeneratorError::InvalidConfig` if the URL is invalid.
+    pub fn new(config: &RedisConfig, buffer_config: &BufferConfig) -> Result<Self, GeneratorError> {
+        Ok(Self::with_generator(RedisGenerator::new(config)?, buffer_config))
+    }
+
+    /// Creates a new `BufferedRedisGenerator` reserving blocks from `redis_generator`.
+    pub fn with_generator(redis_generator: RedisGenerator, buffer_config: &BufferConfig) -> Self {
+        Self {
+            redis_generator: Arc::new(redis_generator),
+            buffer: Arc::new(Mutex::new(VecDeque::new())),
+            refill: Arc::new(Mutex::new(())),
+            buffer_config: buffer_config.clone(),
+        }
+    }
+
+    /// Reserves the next block in a background task, unless a refill is already in flight.
+    fn spawn_refill(&self) {
+        let Ok(refill) = self.refill.clone().try_lock_owned() else {
+            return;
+        };
+        let redis_generator = self.redis_generator.clone();
+        let buffer = self.buffer.clone();
+        let size = self.buffer_config.size;
+        tokio::spawn(async move {
+            let _refill = refill;
+            match GeneratorInteger::generate_keys(redis_generator.as_ref(), size).await {
+                Ok(block) => buffer.lock().await.extend(block),
+                Err(err) => warn!(error_code = err.code(), "Failed to refill the counter buffer: {err}"),
+            }
+        });
+    }
+}
+
+
+#[async_trait]
+impl GeneratorInteger for BufferedRedisGenerator {
+    /// Hands out the next buffered counter value, starting a background refill once fewer
+    /// than `refill_below` values are left. An empty buffer waits for the refill in flight,
+    /// or reserves a new block itself.
+    async fn generate_key(&self) -> Result<u128, GeneratorError> {
+        {
+            let mut buffer = self.buffer.lock().await;
+            if let Some(value) = buffer.pop_front() {
+                if buffer.len() < self.buffer_config.refill_below {
+                    self.spawn_refill();
+                }
+                return Ok(value);
+            }
+        }
+        // The refill lock is always taken before the buffer lock, so a background refill
+        // can complete while this draw waits for it.
+        let _refill = self.refill.lock().await;
+        let mut buffer = self.buffer.lock().await;
+        if buffer.is_empty() {
+            let block = GeneratorInteger::generate_keys(self.redis_generator.as_ref(), self.buffer_config.size).await?;
+            buffer.extend(block);
+        }
+        buffer
+            .pop_front()
+            .ok_or_else(|| GeneratorError::UnknownError("Empty counter block".to_string()))
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.redis_generator.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use tokio::sync::Notify;
+    use crate::generator::redis::{CounterStore, MockCounterStore};
+
+    fn buffered(size: usize, refill_below: usize, counter: Arc<AtomicU64>, refills: Arc<AtomicU64>) -> BufferedRedisGenerator {
+        let mut store = MockCounterStore::new();
+        store.expect_incr_by().returning(move |count| {
+            refills.fetch_add(1, Ordering::SeqCst);
+            Ok((counter.fetch_add(count, Ordering::SeqCst) + count).to_string())
+        });
+        let redis_generator = RedisGenerator::with_store(Arc::new(store), None);
+        BufferedRedisGenerator::with_generator(redis_generator, &BufferConfig { size, refill_below })
+    }
+
+    #[tokio::test]
+    async fn test_keys_come_from_one_block() {
+        let refills = Arc::new(AtomicU64::new(0));
+        let generator = buffered(5, 0, Arc::new(AtomicU64::new(0)), refills.clone());
+        for expected in 1..=5 {
+            assert_eq!(generator.generate_key().await, Ok(expected));
+        }
+        assert_eq!(refills.load(Ordering::SeqCst), 1);
+        assert_eq!(generator.generate_key().await, Ok(6));
+        assert_eq!(refills.load(Ordering::SeqCst), 2);
+    }
+
+    /// Waits until `expected` blocks have been reserved.
+    async fn wait_for_refills(refills: &AtomicU64, expected: u64) {
+        while refills.load(Ordering::SeqCst) < expected {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refills_below_threshold() {
+        let refills = Arc::new(AtomicU64::new(0));
+        let generator = buffered(5, 2, Arc::new(AtomicU64::new(0)), refills.clone());
+        for expected in 1..=4 {
+            assert_eq!(generator.generate_key().await, Ok(expected));
+        }
+        wait_for_refills(&refills, 2).await;
+        let _refill = generator.refill.lock().await;
+        assert_eq!(generator.buffer.lock().await.len(), 6);
+        assert_eq!(refills.load(Ordering::SeqCst), 2);
+    }
+
+    /// A store that reserves the first block at once and every later one once the test
+    /// opens the gate.
+    #[derive(Debug, Default)]
+    struct GatedStore {
+        gate: Notify,
+        counter: AtomicU64,
+    }
+
+    #[async_trait]
+    impl CounterStore for GatedStore {
+        async fn incr(&self) -> Result<String, GeneratorError> {
+            unreachable!("buffered draws reserve blocks")
+        }
+
+        async fn incr_by(&self, count: u64) -> Result<String, GeneratorError> {
+            if self.counter.load(Ordering::SeqCst) > 0 {
+                self.gate.notified().await;
+            }
+            Ok((self.counter.fetch_add(count, Ordering::SeqCst) + count).to_string())
+        }
+
+        async fn get(&self) -> Result<Option<String>, GeneratorError> {
+            Ok(None)
+        }
+
+        async fn raise_to(&self, value: u128) -> Result<String, GeneratorError> {
+            Ok(value.to_string())
+        }
+
+        async fn ping(&self) -> Result<(), GeneratorError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_draws_do_not_wait_for_background_refill() {
+        let store = Arc::new(GatedStore::default());
+        let redis_generator = RedisGenerator::with_store(store.clone(), None);
+        let generator = Arc::new(BufferedRedisGenerator::with_generator(redis_generator, &BufferConfig { size: 5, refill_below: 4 }));
+        for expected in 1..=5 {
+            assert_eq!(generator.generate_key().await, Ok(expected));
+        }
+
+        // The buffer is empty now, so the next draw waits for the pending refill.
+        let pending = tokio::spawn({
+            let generator = generator.clone();
+            async move { generator.generate_key().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+        store.gate.notify_one();
+        assert_eq!(pending.await.unwrap(), Ok(6));
+        assert_eq!(store.counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_draws_are_unique() {
+        let refills = Arc::new(AtomicU64::new(0));
+        let generator = Arc::new(buffered(1000, 100, Arc::new(AtomicU64::new(0)), refills.clone()));
+        let tasks: Vec<_> = (0..10_000)
+            .map(|_| {
+                let generator = generator.clone();
+                tokio::spawn(async move { generator.generate_key().await })
+            })
+            .collect();
+        let mut keys = HashSet::new();
+        for task in tasks {
+            keys.insert(task.await.unwrap().unwrap());
+        }
+        assert_eq!(keys.len(), 10_000);
+        assert!(refills.load(Ordering::SeqCst) >= 10);
+    }
+}
@@ -0,0 +1,136 @@
+//! This module defines a development-only generator wrapper that remembers recently
+//! issued keys and logs an error whenever a key is issued twice.
+//!
+//! Only the last `capacity` keys are remembered, so duplicates further apart than that
+//! go unnoticed. Memory grows with the capacity: do not enable it in production.
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use tonic::async_trait;
+use tracing::error;
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+
+
+/// The most recently issued keys, bounded to a capacity.
+#[derive(Debug, Default)]
+struct RecentKeys {
+    keys: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+
+/// A generator that reports keys of another generator that were already issued recently.
+#[derive(Debug)]
+pub struct DuplicateDetectorGenerator {
+    inner: Arc<dyn Generator>,
+    recent: Mutex<RecentKeys>,
+    capacity: usize,
+}
+
+
+impl DuplicateDetectorGenerator {
+    /// Creates a new `DuplicateDetectorGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `capacity` - How many recent keys are remembered.
+    pub fn new(inner: Arc<dyn Generator>, capacity: usize) -> Self {
+        Self { inner, recent: Mutex::new(RecentKeys::default()), capacity }
+    }
+
+    /// Records an issued key, evicting the oldest one once the capacity is reached.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the key was already among the recent keys.
+    fn observe(&self, key: &str) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.keys.contains(key) {
+            return true;
+        }
+        if recent.order.len() >= self.capacity {
+            if let Some(oldest) = recent.order.pop_front() {
+                recent.keys.remove(&oldest);
+            }
+        }
+        recent.keys.insert(key.to_string());
+        recent.order.push_back(key.to_string());
+        false
+    }
+}
+
+
+#[async_trait]
+impl Generator for DuplicateDetectorGenerator {
+    /// Generates a key from the wrapped generator, logging an error if it is a duplicate.
+    /// The key is returned either way.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        let key = self.inner.generate_key().await?;
+        if self.observe(&key) {
+            error!("Duplicate key issued: {key}");
+        }
+        Ok(key)
+    }
+
+    /// Generates a batch from the wrapped generator, logging an error for every duplicate in it.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        let keys = self.inner.generate_keys(count).await?;
+        for key in &keys {
+            if self.observe(key) {
+                error!("Duplicate key issued: {key}");
+            }
+        }
+        Ok(keys)
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        self.inner.collision_probability(issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.inner.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+    use crate::generator::MockGenerator;
+
+    fn detecting(keys: Vec<&'static str>, capacity: usize) -> DuplicateDetectorGenerator {
+        let mut mock_gen = MockGenerator::new();
+        let mut keys = keys.into_iter();
+        mock_gen.expect_generate_key().returning(move || Ok(keys.next().unwrap().to_string()));
+        DuplicateDetectorGenerator::new(Arc::new(mock_gen), capacity)
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_duplicate_is_logged() {
+        let generator = detecting(vec!["aaaaaaa1", "aaaaaaa2", "aaaaaaa1"], 10);
+        for _ in 0..2 {
+            generator.generate_key().await.unwrap();
+        }
+        assert!(!logs_contain("Duplicate key issued"));
+        assert_eq!(generator.generate_key().await.unwrap(), "aaaaaaa1");
+        assert!(logs_contain("Duplicate key issued: aaaaaaa1"));
+    }
+
+    #[test]
+    fn test_capacity_is_bounded() {
+        let generator = detecting(vec![], 2);
+        assert!(!generator.observe("aaaaaaa1"));
+        assert!(!generator.observe("aaaaaaa2"));
+        assert!(generator.observe("aaaaaaa2"));
+        assert!(!generator.observe("aaaaaaa3"));
+        assert!(!generator.observe("aaaaaaa1"));
+        assert_eq!(generator.recent.lock().unwrap().order.len(), 2);
+    }
+}
@@ -0,0 +1,321 @@
+//! This file defines the encodings turning integer keys into strings, selected with
+//! the `KEY_ENCODING` environment variable.
+//!
+//! Crockford base 32 is meant for keys people read aloud or type: it is case-insensitive
+//! and leaves out I, L, O and U. Decoding is lenient, accepting both cases, treating
+//! I and L as 1 and O as 0, and ignoring hyphens. An optional trailing check symbol,
+//! the value modulo 37, catches most transcription mistakes.
+//!
+//! The base 62 alphabet can be replaced with the `ALPHABET` environment variable, e.g.
+//! to reorder the symbols. It is installed once at startup, before any key is encoded,
+//! and so is the encoding itself.
+use std::sync::OnceLock;
+use crate::config::var;
+use crate::generator::ALPHABET;
+use crate::generator::error::GeneratorError;
+
+/// The uppercase base 36 alphabet, in order of value. Every symbol is in the QR code
+/// alphanumeric mode, which encodes it more densely than byte mode.
+pub const BASE36_ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// The Crockford base 32 alphabet, in order of value.
+pub const CROCKFORD_ALPHABET: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// The Crockford check symbols for the check values 32 to 36.
+const CROCKFORD_CHECK_EXTRA: &str = "*~$=U";
+
+/// The Crockford check value modulus.
+const CROCKFORD_CHECK_MODULUS: u128 = 37;
+
+/// The base 62 alphabet set with `ALPHABET`, if any.
+static CUSTOM_ALPHABET: OnceLock<&'static str> = OnceLock::new();
+
+/// The key encoding, installed at startup or read from the environment on first use.
+static KEY_ENCODING: OnceLock<Encoding> = OnceLock::new();
+
+
+/// `Encoding` defines how integer keys are written as strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Case-sensitive base 62 over `ALPHABET`, or the alphabet installed with `install_alphabet`.
+    Base62,
+    /// Uppercase base 36, for keys printed as QR codes.
+    Base36,
+    /// Crockford base 32, optionally followed by a check symbol.
+    Crockford32 { check_symbol: bool },
+}
+
+
+impl Encoding {
+    /// Returns the encoding named by a `KEY_ENCODING` value.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - One of `base62`, `base36` or `crockford32`.
+    /// * `check_symbol` - Whether keys carry a check symbol; only Crockford base 32 supports it.
+    ///
+    /// # Returns
+    ///
+    /// The encoding, or `None` if the combination is not supported.
+    pub fn from_name(name: &str, check_symbol: bool) -> Option<Self> {
+        match (name, check_symbol) {
+            ("base62", false) => Some(Encoding::Base62),
+            ("base36", false) => Some(Encoding::Base36),
+            ("crockford32", check_symbol) => Some(Encoding::Crockford32 { check_symbol }),
+            _ => None,
+        }
+    }
+
+    /// Returns the digit symbols, in order of value.
+    pub fn symbols(&self) -> &'static str {
+        match self {
+            Encoding::Base62 => CUSTOM_ALPHABET.get().copied().unwrap_or(ALPHABET),
+            Encoding::Base36 => BASE36_ALPHABET,
+            Encoding::Crockford32 { .. } => CROCKFORD_ALPHABET,
+        }
+    }
+
+    /// Returns the base of the encoding.
+    pub fn base(&self) -> u128 {
+        self.symbols().len() as u128
+    }
+
+    /// Returns the largest number of digits whose values all fit in a `u128`.
+    pub fn max_digits(&self) -> usize {
+        let mut digits = 0;
+        while self.base().checked_pow(digits as u32 + 1).is_some() {
+            digits += 1;
+        }
+        digits
+    }
+
+    /// Returns whether keys end with a check symbol.
+    pub fn has_check_symbol(&self) -> bool {
+        matches!(self, Encoding::Crockford32 { check_symbol: true })
+    }
+
+    /// Returns the length of a key with `digits` digits, including the check symbol.
+    pub fn key_length(&self, digits: usize) -> usize {
+        digits + usize::from(self.has_check_symbol())
+    }
+
+    /// Returns whether `c` can appear in an encoded key.
+    pub fn is_key_char(&self, c: char) -> bool {
+        self.symbols().contains(c) || (self.has_check_symbol() && CROCKFORD_CHECK_EXTRA.contains(c))
+    }
+
+    /// Encodes `number` as exactly `digits` digits, followed by the check symbol if enabled.
+    /// The caller must ensure the number fits in `digits` digits.
+    pub fn encode(&self, number: u128, digits: usize) -> String {
+        self.encode_with(self.symbols(), number, digits)
+    }
+
+    /// Encodes `number` like `encode`, but over the digit symbols `symbols`.
+    fn encode_with(&self, symbols: &str, number: u128, digits: usize) -> String {
+        let symbols = symbols.as_bytes();
+        let base = self.base();
+        let mut result = Vec::with_capacity(self.key_length(digits));
+        let mut num = number;
+        for _ in 0..digits {
+            result.push(symbols[(num % base) as usize] as char);
+            num /= base;
+        }
+        result.reverse();
+        if self.has_check_symbol() {
+            result.push(check_symbol(number));
+        }
+        result.into_iter().collect()
+    }
+
+    /// Decodes a key back into its integer value.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The encoded key. Base 36 keys are case-insensitive and Crockford keys
+    ///   are decoded leniently.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the value, or a `GeneratorError::InvalidKey` naming the failed check.
+    pub fn decode(&self, key: &str) -> Result<u128, GeneratorError> {
+        self.decode_with(self.symbols(), key)
+    }
+
+    /// Decodes `key` like `decode`, but over the digit symbols `symbols`.
+    fn decode_with(&self, symbols: &str, key: &str) -> Result<u128, GeneratorError> {
+        let invalid = |check| GeneratorError::InvalidKey { key: key.to_string(), check };
+        let mut chars: Vec<char> = match self {
+            Encoding::Base62 => key.chars().collect(),
+            Encoding::Base36 => key.chars().map(|c| c.to_ascii_uppercase()).collect(),
+            Encoding::Crockford32 { .. } => key
+                .chars()
+                .filter(|c| *c != '-')
+                .map(|c| match c.to_ascii_uppercase() {
+                    'I' | 'L' => '1',
+                    'O' => '0',
+                    c => c,
+                })
+                .collect(),
+        };
+        let check = if self.has_check_symbol() { Some(chars.pop().ok_or_else(|| invalid("length"))?) } else { None };
+
+        let mut value: u128 = 0;
+        for c in chars {
+            let digit = symbols.find(c).ok_or_else(|| invalid("alphabet"))? as u128;
+            value = value
+                .checked_mul(self.base())
+                .and_then(|value| value.checked_add(digit))
+                .ok_or_else(|| invalid("length"))?;
+        }
+        if check.is_some_and(|check| check != check_symbol(value)) {
+            return Err(invalid("check symbol"));
+        }
+        Ok(value)
+    }
+}
+
+
+/// Returns the Crockford check symbol of `number`.
+fn check_symbol(number: u128) -> char {
+    let index = (number % CROCKFORD_CHECK_MODULUS) as usize;
+    CROCKFORD_ALPHABET.chars().chain(CROCKFORD_CHECK_EXTRA.chars()).nth(index).unwrap()
+}
+
+
+/// Replaces the base 62 alphabet for the rest of the process. Only the first call has an
+/// effect, so it must run at startup before any key is encoded.
+///
+/// # Arguments
+///
+/// * `alphabet` - 62 unique ASCII symbols, in order of value.
+pub(crate) fn install_alphabet(alphabet: String) {
+    let _ = CUSTOM_ALPHABET.set(Box::leak(alphabet.into_boxed_str()));
+}
+
+
+/// Sets the key encoding for the rest of the process. Only the first call has an
+/// effect, so it must run at startup before any key is encoded.
+///
+/// # Arguments
+///
+/// * `encoding` - The encoding validated by the configuration.
+pub(crate) fn install_encoding(encoding: Encoding) {
+    let _ = KEY_ENCODING.set(encoding);
+}
+
+
+/// Returns the key encoding. Unless one was installed with `install_encoding`, it is
+/// determined from the `KEY_ENCODING` and `KEY_CHECK_SYMBOL` environment variables on
+/// the first call, so later changes to the environment do not change it.
+///
+/// # Returns
+///
+/// The encoding, defaulting to base 62 if the variables are not set or invalid.
+pub(crate) fn key_encoding() -> Encoding {
+    *KEY_ENCODING.get_or_init(encoding_from_env)
+}
+
+
+/// Determines the key encoding from the `KEY_ENCODING` and `KEY_CHECK_SYMBOL`
+/// environment variables, defaulting to base 62 if they are not set or invalid.
+fn encoding_from_env() -> Encoding {
+    let name = var("KEY_ENCODING").unwrap_or("base62".to_string());
+    let check_symbol = var("KEY_CHECK_SYMBOL").is_ok_and(|value| value == "true");
+    Encoding::from_name(&name, check_symbol).unwrap_or(Encoding::Base62)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CROCKFORD: Encoding = Encoding::Crockford32 { check_symbol: false };
+    const CROCKFORD_CHECKED: Encoding = Encoding::Crockford32 { check_symbol: true };
+
+    #[test]
+    fn test_crockford_encode() {
+        assert_eq!(CROCKFORD.encode(0, 8), "00000000");
+        assert_eq!(CROCKFORD.encode(31, 8), "0000000Z");
+        assert_eq!(CROCKFORD.encode(32, 8), "00000010");
+        assert_eq!(CROCKFORD.encode(1234, 8), "0000016J");
+        assert_eq!(CROCKFORD.encode(32_u128.pow(8) - 1, 8), "ZZZZZZZZ");
+    }
+
+    #[test]
+    fn test_crockford_lenient_decode() {
+        assert_eq!(CROCKFORD.decode("0000016J").unwrap(), 1234);
+        assert_eq!(CROCKFORD.decode("0000016j").unwrap(), 1234);
+        assert_eq!(CROCKFORD.decode("ooooo16j").unwrap(), 1234);
+        assert_eq!(CROCKFORD.decode("0000-0l6J").unwrap(), 1234);
+        assert_eq!(CROCKFORD.decode("0000i6J").unwrap(), 1234);
+        assert_eq!(
+            CROCKFORD.decode("0000016U").unwrap_err(),
+            GeneratorError::InvalidKey { key: "0000016U".to_string(), check: "alphabet" }
+        );
+    }
+
+    #[test]
+    fn test_crockford_check_symbol() {
+        assert_eq!(CROCKFORD_CHECKED.encode(1234, 8), "0000016JD");
+        assert_eq!(CROCKFORD_CHECKED.encode(36, 8), "00000014U");
+        assert_eq!(CROCKFORD_CHECKED.key_length(8), 9);
+        assert_eq!(CROCKFORD_CHECKED.decode("0000016jd").unwrap(), 1234);
+        assert_eq!(CROCKFORD_CHECKED.decode("00000014u").unwrap(), 36);
+        assert_eq!(
+            CROCKFORD_CHECKED.decode("0000016JE").unwrap_err(),
+            GeneratorError::InvalidKey { key: "0000016JE".to_string(), check: "check symbol" }
+        );
+    }
+
+    #[test]
+    fn test_base62_round_trip() {
+        assert_eq!(Encoding::Base62.encode(12345678, 8), "0000pnfq");
+        assert_eq!(Encoding::Base62.decode("0000pnfq").unwrap(), 12345678);
+        assert!(Encoding::Base62.decode("0000pnf-").is_err());
+    }
+
+    #[test]
+    fn test_custom_alphabet_remaps_digits() {
+        let reversed: String = ALPHABET.chars().rev().collect();
+        assert_eq!(Encoding::Base62.encode_with(&reversed, 0, 4), "zzzz");
+        assert_eq!(Encoding::Base62.encode_with(&reversed, 61, 4), "zzz0");
+        assert_eq!(Encoding::Base62.encode_with(&reversed, 12345678, 4), "ACK9");
+        assert_eq!(Encoding::Base62.decode_with(&reversed, "zzy0").unwrap(), 62 + 61);
+    }
+
+    #[test]
+    fn test_base36_round_trip() {
+        assert_eq!(Encoding::Base36.encode(0, 8), "00000000");
+        assert_eq!(Encoding::Base36.encode(35, 8), "0000000Z");
+        assert_eq!(Encoding::Base36.encode(36, 8), "00000010");
+        assert_eq!(Encoding::Base36.encode(36_u128.pow(8) - 1, 8), "ZZZZZZZZ");
+        assert_eq!(Encoding::Base36.encode(1234567890, 8), "00KF12OI");
+        assert_eq!(Encoding::Base36.decode("00KF12OI").unwrap(), 1234567890);
+        assert_eq!(Encoding::Base36.decode("00kf12oi").unwrap(), 1234567890);
+        assert!(Encoding::Base36.decode("00KF12O-").is_err());
+    }
+
+    #[test]
+    fn test_key_encoding_is_read_once() {
+        let encoding = key_encoding();
+        assert_eq!(KEY_ENCODING.get(), Some(&encoding));
+        install_encoding(Encoding::Base36);
+        assert_eq!(key_encoding(), encoding);
+    }
+
+    #[test]
+    fn test_max_digits() {
+        assert_eq!(Encoding::Base62.max_digits(), 21);
+        assert_eq!(Encoding::Base36.max_digits(), 24);
+        assert_eq!(CROCKFORD.max_digits(), 25);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Encoding::from_name("base62", false), Some(Encoding::Base62));
+        assert_eq!(Encoding::from_name("crockford32", true), Some(CROCKFORD_CHECKED));
+        assert_eq!(Encoding::from_name("base36", false), Some(Encoding::Base36));
+        assert_eq!(Encoding::from_name("base62", true), None);
+        assert_eq!(Encoding::from_name("base64", false), None);
+    }
+}
@@ -15,6 +15,98 @@ pub enum GeneratorError {
     /// An unknown or unexpected error occurred.
     #[error("Generator unknown error: {0}")]
     UnknownError(String),
+    /// The counter returned a value not higher than one already observed,
+    /// e.g. a stale replica after a failover. Retrying later is safe.
+    #[error("Stale counter: got {observed}, already saw {last_seen}")]
+    StaleCounter { observed: u128, last_seen: u128 },
+    /// A retry or re-roll loop gave up without producing a key.
+    #[error("Retries exhausted: {0}")]
+    RetriesExhausted(String),
+    /// A generated key does not have the expected format.
+    #[error("Key {key} failed the {check} check")]
+    InvalidKey { key: String, check: &'static str },
+    /// The maintenance pool has handed out every key.
+    #[error("Maintenance pool exhausted")]
+    PoolExhausted,
+    /// The fixed test sequence has no keys left.
+    #[error("Fixed sequence exhausted")]
+    SequenceExhausted,
+    /// The counter is past the number of keys the generator can issue without repeating.
+    #[error("Counter {counter} exceeds the generator capacity {capacity}")]
+    OutOfRange { counter: u128, capacity: u128 },
+    /// The counter store accepts reads but rejects writes, e.g. a read-only replica
+    /// during maintenance.
+    #[error("Counter store is read-only")]
+    ReadOnly,
+    /// The counter returned a value far below one already observed, so it was lost,
+    /// e.g. evicted, and restarted. Retrying would reissue keys.
+    #[error("Counter lost: got {observed}, already saw {last_seen}")]
+    CounterLost { observed: u128, last_seen: u128 },
+    /// Every generator of a failover chain failed, in the order they were tried.
+    #[error("All generators failed: {}", summarize(.0))]
+    AllGeneratorsFailed(Vec<GeneratorError>),
+    /// The generator configuration is invalid, e.g. a malformed Redis URL.
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    /// The configured number of keys was already issued in the current window.
+    #[error("Window limit of {limit} keys reached")]
+    WindowLimitExceeded { limit: u64 },
+    /// The counter is past the largest key of the configured length, so `NUMBER_DIGITS`
+    /// must grow or keys must move to a new namespace.
+    #[error("Number {number} exceeds the maximum key {max}")]
+    KeySpaceExhausted { number: u128, max: u128 },
+}
+
+
+/// Joins the messages of several errors, in order.
+fn summarize(errors: &[GeneratorError]) -> String {
+    errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("; ")
+}
+
+
+impl GeneratorError {
+    /// Returns the stable, machine-readable code of the error, logged as `error_code`
+    /// so alerts do not depend on error messages.
+    pub fn code(&self) -> &'static str {
+        match self {
+            GeneratorError::ConnectionError => "KGEN-REDIS-001",
+            GeneratorError::StaleCounter { .. } => "KGEN-REDIS-002",
+            GeneratorError::ReadOnly => "KGEN-REDIS-003",
+            GeneratorError::CounterLost { .. } => "KGEN-REDIS-004",
+            GeneratorError::GeneratorNotFound => "KGEN-GEN-001",
+            GeneratorError::UnknownError(_) => "KGEN-GEN-002",
+            GeneratorError::RetriesExhausted(_) => "KGEN-GEN-003",
+            GeneratorError::AllGeneratorsFailed(_) => "KGEN-GEN-004",
+            GeneratorError::InvalidConfig(_) => "KGEN-CFG-001",
+            GeneratorError::InvalidKey { .. } => "KGEN-KEY-001",
+            GeneratorError::OutOfRange { .. } => "KGEN-CAP-001",
+            GeneratorError::PoolExhausted => "KGEN-CAP-002",
+            GeneratorError::SequenceExhausted => "KGEN-CAP-003",
+            GeneratorError::WindowLimitExceeded { .. } => "KGEN-CAP-004",
+            GeneratorError::KeySpaceExhausted { .. } => "KGEN-CAP-005",
+        }
+    }
+
+    /// Returns the name of the variant, recorded as the `error` field of request spans.
+    pub fn variant(&self) -> &'static str {
+        match self {
+            GeneratorError::ConnectionError => "ConnectionError",
+            GeneratorError::GeneratorNotFound => "GeneratorNotFound",
+            GeneratorError::UnknownError(_) => "UnknownError",
+            GeneratorError::StaleCounter { .. } => "StaleCounter",
+            GeneratorError::RetriesExhausted(_) => "RetriesExhausted",
+            GeneratorError::InvalidKey { .. } => "InvalidKey",
+            GeneratorError::PoolExhausted => "PoolExhausted",
+            GeneratorError::SequenceExhausted => "SequenceExhausted",
+            GeneratorError::OutOfRange { .. } => "OutOfRange",
+            GeneratorError::ReadOnly => "ReadOnly",
+            GeneratorError::CounterLost { .. } => "CounterLost",
+            GeneratorError::AllGeneratorsFailed(_) => "AllGeneratorsFailed",
+            GeneratorError::InvalidConfig(_) => "InvalidConfig",
+            GeneratorError::WindowLimitExceeded { .. } => "WindowLimitExceeded",
+            GeneratorError::KeySpaceExhausted { .. } => "KeySpaceExhausted",
+        }
+    }
 }
 
 
@@ -26,6 +118,18 @@ impl From<GeneratorError> for Status {
             GeneratorError::ConnectionError => Status::unavailable("Connection error"),
             GeneratorError::GeneratorNotFound => Status::not_found("Generator not found"),
             GeneratorError::UnknownError(error) => Status::internal(format!("Generator error: {error}")),
+            GeneratorError::StaleCounter { .. } => Status::unavailable("Stale counter"),
+            GeneratorError::RetriesExhausted(reason) => Status::unavailable(format!("Retries exhausted: {reason}")),
+            GeneratorError::InvalidKey { check, .. } => Status::internal(format!("Generated key failed the {check} check")),
+            GeneratorError::PoolExhausted => Status::unavailable("Maintenance pool exhausted"),
+            GeneratorError::SequenceExhausted => Status::unavailable("Fixed sequence exhausted"),
+            GeneratorError::OutOfRange { .. } => Status::out_of_range("Generator capacity exceeded"),
+            GeneratorError::ReadOnly => Status::unavailable("Counter store is read-only"),
+            GeneratorError::AllGeneratorsFailed(errors) => Status::unavailable(format!("All generators failed: {}", summarize(&errors))),
+            GeneratorError::InvalidConfig(error) => Status::internal(format!("Invalid configuration: {error}")),
+            GeneratorError::CounterLost { .. } => Status::data_loss("Counter loss detected"),
+            GeneratorError::WindowLimitExceeded { .. } => Status::resource_exhausted("Generation window limit reached"),
+            GeneratorError::KeySpaceExhausted { .. } => Status::resource_exhausted("Key space exhausted"),
         }
     }
 }
@@ -35,6 +139,37 @@ impl From<GeneratorError> for Status {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_generator_error_variants() {
+        assert_eq!(GeneratorError::ReadOnly.variant(), "ReadOnly");
+        assert_eq!(GeneratorError::UnknownError("Some error".to_string()).variant(), "UnknownError");
+        assert_eq!(GeneratorError::KeySpaceExhausted { number: 10, max: 9 }.variant(), "KeySpaceExhausted");
+    }
+
+    #[test]
+    fn test_generator_error_codes() {
+        let codes = [
+            (GeneratorError::ConnectionError, "KGEN-REDIS-001"),
+            (GeneratorError::StaleCounter { observed: 5, last_seen: 10 }, "KGEN-REDIS-002"),
+            (GeneratorError::ReadOnly, "KGEN-REDIS-003"),
+            (GeneratorError::CounterLost { observed: 1, last_seen: 10 }, "KGEN-REDIS-004"),
+            (GeneratorError::GeneratorNotFound, "KGEN-GEN-001"),
+            (GeneratorError::UnknownError("Some error".to_string()), "KGEN-GEN-002"),
+            (GeneratorError::RetriesExhausted("blocked".to_string()), "KGEN-GEN-003"),
+            (GeneratorError::AllGeneratorsFailed(vec![GeneratorError::ConnectionError]), "KGEN-GEN-004"),
+            (GeneratorError::InvalidConfig("bad url".to_string()), "KGEN-CFG-001"),
+            (GeneratorError::InvalidKey { key: "abc".to_string(), check: "length" }, "KGEN-KEY-001"),
+            (GeneratorError::OutOfRange { counter: 11, capacity: 10 }, "KGEN-CAP-001"),
+            (GeneratorError::PoolExhausted, "KGEN-CAP-002"),
+            (GeneratorError::SequenceExhausted, "KGEN-CAP-003"),
+            (GeneratorError::WindowLimitExceeded { limit: 100 }, "KGEN-CAP-004"),
+            (GeneratorError::KeySpaceExhausted { number: 63, max: 62 }, "KGEN-CAP-005"),
+        ];
+        for (err, code) in codes {
+            assert_eq!(err.code(), code, "{err}");
+        }
+    }
+
     #[test]
     fn test_generator_error_conversion() {
         let connection_error = GeneratorError::ConnectionError;
@@ -51,5 +186,56 @@ mod tests {
         let status: Status = unknown_error.into();
         assert_eq!(status.code(), tonic::Code::Internal);
         assert_eq!(status.message(), "Generator error: Some error");
+
+        let stale_error = GeneratorError::StaleCounter { observed: 5, last_seen: 10 };
+        let status: Status = stale_error.into();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "Stale counter");
+
+        let retries_error = GeneratorError::RetriesExhausted("blocked".to_string());
+        let status: Status = retries_error.into();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "Retries exhausted: blocked");
+
+        let invalid_error = GeneratorError::InvalidKey { key: "abc".to_string(), check: "length" };
+        let status: Status = invalid_error.into();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(status.message(), "Generated key failed the length check");
+
+        let status: Status = GeneratorError::PoolExhausted.into();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "Maintenance pool exhausted");
+
+        let status: Status = GeneratorError::SequenceExhausted.into();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "Fixed sequence exhausted");
+
+        let status: Status = GeneratorError::OutOfRange { counter: 11, capacity: 10 }.into();
+        assert_eq!(status.code(), tonic::Code::OutOfRange);
+        assert_eq!(status.message(), "Generator capacity exceeded");
+
+        let status: Status = GeneratorError::ReadOnly.into();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "Counter store is read-only");
+
+        let status: Status = GeneratorError::AllGeneratorsFailed(vec![GeneratorError::ConnectionError, GeneratorError::ReadOnly]).into();
+        assert_eq!(status.code(), tonic::Code::Unavailable);
+        assert_eq!(status.message(), "All generators failed: Connection error; Counter store is read-only");
+
+        let status: Status = GeneratorError::InvalidConfig("bad url".to_string()).into();
+        assert_eq!(status.code(), tonic::Code::Internal);
+        assert_eq!(status.message(), "Invalid configuration: bad url");
+
+        let status: Status = GeneratorError::CounterLost { observed: 1, last_seen: 10 }.into();
+        assert_eq!(status.code(), tonic::Code::DataLoss);
+        assert_eq!(status.message(), "Counter loss detected");
+
+        let status: Status = GeneratorError::WindowLimitExceeded { limit: 100 }.into();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.message(), "Generation window limit reached");
+
+        let status: Status = GeneratorError::KeySpaceExhausted { number: 63, max: 62 }.into();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.message(), "Key space exhausted");
     }
 }
@@ -0,0 +1,149 @@
+/// This file is part of the `generator` module. It tracks the number of digits of the
+/// generated keys, which grows past `NUMBER_DIGITS` when `AUTO_EXPAND` is enabled and a
+/// counter outgrows the keyspace.
+///
+/// Keys of different lengths never collide, so keys issued before an expansion stay
+/// valid and decode to the same counter values. The length is kept in memory only: after
+/// a restart, the first key past the keyspace expands it again.
+///
+/// With `REGION_TAG` or `SCHEME_PREFIX`, the leading digit is reserved for the tag:
+/// numbers are encoded with one significant digit less, so the leading digit is always
+/// zero and the tag never overwrites part of a number.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::warn;
+use crate::generator::error::GeneratorError;
+use crate::generator::encoding::key_encoding;
+use crate::generator::max_number_for;
+
+
+/// The key length of the process, shared by every generator.
+pub static KEY_LENGTH: KeyLength = KeyLength::new();
+
+
+/// `KeyLength` counts how many digits keys have grown by, up to a configured cap.
+#[derive(Debug)]
+pub struct KeyLength {
+    /// How many digits keys have grown by so far.
+    expansions: AtomicUsize,
+    /// How many digits keys may grow by, 0 unless `AUTO_EXPAND` is enabled.
+    max_expansions: AtomicUsize,
+    /// How many leading digits are reserved for a tag and always zero.
+    reserved: AtomicUsize,
+}
+
+
+impl KeyLength {
+    /// Creates a `KeyLength` that never expands.
+    pub const fn new() -> Self {
+        Self { expansions: AtomicUsize::new(0), max_expansions: AtomicUsize::new(0), reserved: AtomicUsize::new(0) }
+    }
+
+    /// Allows keys to grow by up to `max_expansions` digits.
+    pub fn enable_auto_expand(&self, max_expansions: usize) {
+        self.max_expansions.store(max_expansions, Ordering::SeqCst);
+    }
+
+    /// Reserves the leading digit of every key for a region tag or scheme prefix. Must
+    /// run at startup before any generator is created.
+    pub fn reserve_leading_digit(&self) {
+        self.reserved.store(1, Ordering::SeqCst);
+    }
+
+    /// Returns how many leading digits are reserved for a tag.
+    pub fn reserved_digits(&self) -> usize {
+        self.reserved.load(Ordering::SeqCst)
+    }
+
+    /// Returns the current number of digits of keys configured with `base_digits` digits.
+    pub fn current_digits(&self, base_digits: usize) -> usize {
+        base_digits + self.expansions.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of digits `number` is encoded with, expanding the keys by one
+    /// digit at a time while it does not fit and the cap allows it. The reserved leading
+    /// digits are counted in the result but never hold part of `number`.
+    ///
+    /// # Arguments
+    ///
+    /// * `number` - The number to encode.
+    /// * `base_digits` - The configured number of digits.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the number of digits, or a `GeneratorError::KeySpaceExhausted` if
+    /// `number` does not fit in the longest allowed keys.
+    pub fn digits_for(&self, number: u128, base_digits: usize) -> Result<usize, GeneratorError> {
+        loop {
+            let expansions = self.expansions.load(Ordering::SeqCst);
+            let digits = base_digits + expansions;
+            let max = max_number_for(digits.saturating_sub(self.reserved_digits()));
+            if number <= max {
+                return Ok(digits);
+            }
+            if expansions >= self.max_expansions.load(Ordering::SeqCst) || digits >= key_encoding().max_digits() {
+                return Err(GeneratorError::KeySpaceExhausted { number, max });
+            }
+            if self.expansions.compare_exchange(expansions, expansions + 1, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+                warn!("Keyspace of {digits} digits exhausted, expanding keys to {} digits", digits + 1);
+            }
+        }
+    }
+}
+
+
+impl Default for KeyLength {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    #[test]
+    fn test_disabled_does_not_expand() {
+        let length = KeyLength::new();
+        assert_eq!(length.digits_for(max_number_for(2), 2), Ok(2));
+        assert!(length.digits_for(max_number_for(2) + 1, 2).is_err());
+        assert_eq!(length.current_digits(2), 2);
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_crossing_the_keyspace_expands() {
+        let length = KeyLength::new();
+        length.enable_auto_expand(1);
+        assert_eq!(length.digits_for(max_number_for(2) + 1, 2), Ok(3));
+        assert!(logs_contain("Keyspace of 2 digits exhausted, expanding keys to 3 digits"));
+        assert_eq!(length.current_digits(2), 3);
+        assert_eq!(length.digits_for(5, 2), Ok(3));
+    }
+
+    #[test]
+    fn test_expansions_are_capped() {
+        let length = KeyLength::new();
+        length.enable_auto_expand(1);
+        assert_eq!(
+            length.digits_for(max_number_for(3) + 1, 2),
+            Err(GeneratorError::KeySpaceExhausted { number: max_number_for(3) + 1, max: max_number_for(3) })
+        );
+        assert_eq!(length.current_digits(2), 3);
+    }
+
+    #[test]
+    fn test_reserved_digit_is_never_used() {
+        let length = KeyLength::new();
+        length.reserve_leading_digit();
+        assert_eq!(length.digits_for(max_number_for(2), 3), Ok(3));
+        assert_eq!(
+            length.digits_for(max_number_for(2) + 1, 3),
+            Err(GeneratorError::KeySpaceExhausted { number: max_number_for(2) + 1, max: max_number_for(2) })
+        );
+        length.enable_auto_expand(1);
+        assert_eq!(length.digits_for(max_number_for(2) + 1, 3), Ok(4));
+    }
+}
@@ -6,6 +6,14 @@ use crate::generator::Generator;
 use crate::generator::random::RandomGenerator;
 use crate::generator::redis::RedisGenerator;
 use crate::generator::primitive_root_redis::PrimitiveRootRedisGenerator;
+use crate::generator::standby_redis::StandbyRedisGenerator;
+use crate::generator::maintenance_pool::MaintenancePoolGenerator;
+use crate::generator::sequence_fixed::SequenceFixedGenerator;
+use crate::generator::uuid::UuidGenerator;
+use crate::generator::ulid::UlidGenerator;
+use crate::generator::nanoid::NanoidGenerator;
+use crate::generator::buffered_redis::BufferedRedisGenerator;
+use crate::generator::snowflake::SnowflakeGenerator;
 
 
 /// Creates a new key generation layer based on the provided configuration.
@@ -20,18 +28,116 @@ use crate::generator::primitive_root_redis::PrimitiveRootRedisGenerator;
 /// or an error if the generator cannot be created.
 pub async fn new_key_generation_layer(config: &GeneratorConfig) -> Result<Arc<dyn Generator>, Box<dyn Error>> {
     match config { 
-        GeneratorConfig::Random => {
-            let generator = RandomGenerator::new();
+        GeneratorConfig::Random(random_config) => {
+            let generator = RandomGenerator::new(random_config)?;
             Ok(Arc::new(generator))
         },
         GeneratorConfig::Redis(redis_config) => {
-            let generator = RedisGenerator::new(redis_config);
+            let generator = RedisGenerator::new(redis_config)?;
             Ok(Arc::new(generator))
         },
         GeneratorConfig::PrimitiveRootRedis(redis_config, primitive_config) => {
             let generator = PrimitiveRootRedisGenerator::new(redis_config, primitive_config)?;
             Ok(Arc::new(generator))
         },
+        GeneratorConfig::StandbyRedis(redis_config, standby_config) => {
+            let generator = StandbyRedisGenerator::new(redis_config, standby_config)?;
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::MaintenancePool(pool_config) => {
+            let generator = MaintenancePoolGenerator::new(pool_config)?;
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::SequenceFixed(sequence_config) => {
+            let generator = SequenceFixedGenerator::new(sequence_config);
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::Uuid(uuid_config) => {
+            let generator = UuidGenerator::new(uuid_config);
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::Snowflake(snowflake_config) => {
+            let generator = SnowflakeGenerator::new(snowflake_config);
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::BufferedRedis(redis_config, buffer_config) => {
+            let generator = BufferedRedisGenerator::new(redis_config, buffer_config)?;
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::Ulid => {
+            let generator = UlidGenerator::new();
+            Ok(Arc::new(generator))
+        },
+        GeneratorConfig::Nanoid(nanoid_config) => {
+            let generator = NanoidGenerator::new(nanoid_config);
+            Ok(Arc::new(generator))
+        },
         // Add other generator configurations here
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::error::GeneratorError;
+    use crate::config::{BufferConfig, PrimitiveConfig, RedisConfig, RetryConfig};
+    use crate::generator::encoding::key_encoding;
+    use crate::generator::redis::MockCounterStore;
+    use crate::generator::region_tag::RegionTagGenerator;
+
+    fn redis_config() -> RedisConfig {
+        RedisConfig {
+            url: "redis://localhost:6379".to_string(),
+            monotonic_guard: false,
+            pool_size: 1,
+            loss_threshold: None,
+            high_water_file: None,
+            retry: RetryConfig::default(),
+            coalesce: None,
+        }
+    }
+
+    fn counter(value: u64) -> RedisGenerator {
+        let mut store = MockCounterStore::new();
+        store.expect_incr().returning(move || Ok(value.to_string()));
+        store.expect_incr_by().returning(move |count| Ok((value + count - 1).to_string()));
+        RedisGenerator::with_store(Arc::new(store), None)
+    }
+
+    /// Generates a key of `generator` with the `SCHEME_PREFIX` of `config` and splits it
+    /// into the prefix and the decoded remainder.
+    async fn prefixed(generator: Arc<dyn Generator>, config: &GeneratorConfig) -> (char, u128) {
+        let prefixed = RegionTagGenerator::new(generator, config.scheme_prefix().unwrap());
+        let key = prefixed.generate_key().await.unwrap();
+        let mut chars = key.chars();
+        let prefix = chars.next().unwrap();
+        (prefix, key_encoding().decode(chars.as_str()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_counter_generators_emit_their_scheme_prefix() {
+        let redis = GeneratorConfig::Redis(redis_config());
+        assert_eq!(prefixed(Arc::new(counter(12345678)), &redis).await, ('C', 12345678));
+
+        let buffer_config = BufferConfig { size: 1, refill_below: 0 };
+        let buffered = GeneratorConfig::BufferedRedis(redis_config(), buffer_config.clone());
+        let generator = BufferedRedisGenerator::with_generator(counter(62_u128.pow(7) as u64 - 1), &buffer_config);
+        assert_eq!(prefixed(Arc::new(generator), &buffered).await, ('C', 62_u128.pow(7) - 1));
+
+        let primitive_config = PrimitiveConfig { prime: 1000003, start: 0, primitive_root: 2 };
+        let primitive = GeneratorConfig::PrimitiveRootRedis(redis_config(), primitive_config.clone());
+        let generator = PrimitiveRootRedisGenerator::with_generator(counter(30), &primitive_config);
+        assert_eq!(prefixed(Arc::new(generator), &primitive).await, ('P', (1 << 30) % 1000003));
+    }
+
+    #[tokio::test]
+    async fn test_counter_past_the_reserved_digit_is_not_prefixed() {
+        let redis = GeneratorConfig::Redis(redis_config());
+        let prefixed = RegionTagGenerator::new(Arc::new(counter(62_u128.pow(7) as u64)), redis.scheme_prefix().unwrap());
+        assert_eq!(
+            prefixed.generate_key().await,
+            Err(GeneratorError::KeySpaceExhausted { number: 62_u128.pow(7), max: 62_u128.pow(7) - 1 })
+        );
+    }
+}
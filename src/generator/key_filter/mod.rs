@@ -0,0 +1,357 @@
+//! This module defines a generator wrapper that re-rolls keys matching
+//! operator-configured rejection rules.
+//!
+//! Rejected keys are discarded, so with counter-based generators every re-roll
+//! consumes one counter value.
+//!
+//! Custom rules can be written as a Rhai script defining `fn accept(key)`, which
+//! returns whether the key may be issued. The script is sandboxed: Rhai has no file,
+//! network or process access, `print` and `debug` are discarded, and every evaluation
+//! is aborted after a bounded number of operations or a bounded time. Strings, arrays,
+//! maps and the call depth are capped, so a script cannot exhaust memory or the stack.
+use std::cell::Cell;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use regex::Regex;
+use rhai::{Engine, Scope, AST};
+use tonic::async_trait;
+use crate::config::KeyFilterConfig;
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+use crate::instrumentation::{record_retry_exhausted, REASON_KEY_FILTER};
+
+
+/// The longest string a script may build, in bytes.
+const SCRIPT_MAX_STRING_SIZE: usize = 4096;
+/// The most elements an array built by a script may hold.
+const SCRIPT_MAX_ARRAY_SIZE: usize = 1024;
+/// The most entries a map built by a script may hold.
+const SCRIPT_MAX_MAP_SIZE: usize = 1024;
+/// The deepest nesting of function calls in a script.
+const SCRIPT_MAX_CALL_LEVELS: usize = 32;
+/// How many operations run between two checks of the evaluation deadline.
+const SCRIPT_DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+thread_local! {
+    /// The deadline of the script evaluation running on this thread, if any.
+    static SCRIPT_DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+
+/// A compiled key acceptance script.
+#[derive(Debug)]
+pub struct KeyScript {
+    engine: Engine,
+    ast: AST,
+    timeout: Duration,
+}
+
+
+impl KeyScript {
+    /// Compiles a key acceptance script.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The script source, defining `fn accept(key)`.
+    /// * `max_operations` - How many operations one evaluation may run.
+    /// * `timeout` - How long one evaluation may run.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the compiled script, or an error if it does not compile.
+    pub fn compile(source: &str, max_operations: u64, timeout: Duration) -> Result<Self, Box<dyn Error>> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(max_operations);
+        engine.set_max_string_size(SCRIPT_MAX_STRING_SIZE);
+        engine.set_max_array_size(SCRIPT_MAX_ARRAY_SIZE);
+        engine.set_max_map_size(SCRIPT_MAX_MAP_SIZE);
+        engine.set_max_call_levels(SCRIPT_MAX_CALL_LEVELS);
+        engine.on_progress(|operations| {
+            let expired = operations % SCRIPT_DEADLINE_CHECK_INTERVAL == 0
+                && SCRIPT_DEADLINE.get().is_some_and(|deadline| Instant::now() >= deadline);
+            expired.then(|| "timed out".into())
+        });
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        let ast = engine.compile(source)?;
+        Ok(Self { engine, ast, timeout })
+    }
+
+    /// Evaluates `accept(key)`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with whether the key may be issued, or a `GeneratorError` if the
+    /// evaluation fails, runs out of operations or time, or exceeds a size limit.
+    pub fn accepts(&self, key: &str) -> Result<bool, GeneratorError> {
+        SCRIPT_DEADLINE.set(Some(Instant::now() + self.timeout));
+        let accepted = self.engine.call_fn::<bool>(&mut Scope::new(), &self.ast, "accept", (key.to_string(),));
+        SCRIPT_DEADLINE.set(None);
+        accepted.map_err(|err| GeneratorError::UnknownError(format!("Key script failed: {err}")))
+    }
+}
+
+
+/// The compiled rejection rules.
+#[derive(Debug)]
+pub struct KeyFilter {
+    reject_regex: Option<Regex>,
+    /// The denied prefixes, lowercased.
+    deny_prefixes: Vec<String>,
+    script: Option<KeyScript>,
+}
+
+
+impl KeyFilter {
+    /// Compiles the rejection rules of a `KeyFilterConfig`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The key filter configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the compiled rules, or an error if the regex is invalid or the
+    /// script cannot be read or compiled.
+    pub fn new(config: &KeyFilterConfig) -> Result<Self, Box<dyn Error>> {
+        let reject_regex = config.reject_regex.as_deref().map(Regex::new).transpose()?;
+        let deny_prefixes = config.deny_prefixes.iter().map(|prefix| prefix.to_lowercase()).collect();
+        let script = match &config.script_path {
+            Some(path) => {
+                let source = std::fs::read_to_string(path)?;
+                let timeout = Duration::from_millis(config.script_timeout_ms);
+                Some(KeyScript::compile(&source, config.script_max_operations, timeout)?)
+            }
+            None => None,
+        };
+        Ok(Self { reject_regex, deny_prefixes, script })
+    }
+
+    /// Returns whether `key` must not be issued.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with whether the key is rejected, or a `GeneratorError` if the script fails.
+    pub fn rejects(&self, key: &str) -> Result<bool, GeneratorError> {
+        let lowercase = key.to_lowercase();
+        if self.deny_prefixes.iter().any(|prefix| lowercase.starts_with(prefix.as_str()))
+            || self.reject_regex.as_ref().is_some_and(|regex| regex.is_match(key))
+        {
+            return Ok(true);
+        }
+        match &self.script {
+            Some(script) => Ok(!script.accepts(key)?),
+            None => Ok(false),
+        }
+    }
+}
+
+
+/// A generator that re-rolls the keys of another generator rejected by a `KeyFilter`.
+#[derive(Debug)]
+pub struct KeyFilterGenerator {
+    inner: Arc<dyn Generator>,
+    filter: KeyFilter,
+    max_rerolls: usize,
+}
+
+
+impl KeyFilterGenerator {
+    /// Creates a new `KeyFilterGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `filter` - The rejection rules.
+    /// * `max_rerolls` - How many times a rejected key is re-rolled before giving up.
+    pub fn new(inner: Arc<dyn Generator>, filter: KeyFilter, max_rerolls: usize) -> Self {
+        Self { inner, filter, max_rerolls }
+    }
+}
+
+
+#[async_trait]
+impl Generator for KeyFilterGenerator {
+    /// Generates keys from the wrapped generator until one is not rejected.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        for _ in 0..=self.max_rerolls {
+            let key = self.inner.generate_key().await?;
+            if !self.filter.rejects(&key)? {
+                return Ok(key);
+            }
+        }
+        record_retry_exhausted(REASON_KEY_FILTER);
+        Err(GeneratorError::RetriesExhausted("every generated key was rejected".to_string()))
+    }
+
+    /// Generates a batch from the wrapped generator and re-rolls the rejected keys of it one
+    /// by one.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        let mut keys = Vec::with_capacity(count);
+        for key in self.inner.generate_keys(count).await? {
+            if self.filter.rejects(&key)? {
+                keys.push(self.generate_key().await?);
+            } else {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        self.inner.collision_probability(issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.inner.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::MockGenerator;
+    use crate::instrumentation::retry_exhausted_count;
+
+    fn config(reject_regex: Option<&str>, deny_prefixes: &[&str]) -> KeyFilterConfig {
+        KeyFilterConfig {
+            reject_regex: reject_regex.map(String::from),
+            deny_prefixes: deny_prefixes.iter().map(|prefix| prefix.to_string()).collect(),
+            script_path: None,
+            script_max_operations: 100_000,
+            script_timeout_ms: 50,
+            max_rerolls: 10,
+        }
+    }
+
+    fn scripted(keys: Vec<&'static str>, source: &str) -> KeyFilterGenerator {
+        let filter = KeyFilter {
+            reject_regex: None,
+            deny_prefixes: Vec::new(),
+            script: Some(KeyScript::compile(source, 10_000, Duration::from_secs(1)).unwrap()),
+        };
+        KeyFilterGenerator::new(mock_returning(keys), filter, 10)
+    }
+
+    fn mock_returning(keys: Vec<&'static str>) -> Arc<MockGenerator> {
+        let mut mock_gen = MockGenerator::new();
+        let mut keys = keys.into_iter();
+        mock_gen.expect_generate_key().returning(move || Ok(keys.next().unwrap().to_string()));
+        Arc::new(mock_gen)
+    }
+
+    fn filtered(keys: Vec<&'static str>, reject_regex: &str, max_rerolls: usize) -> KeyFilterGenerator {
+        let filter = KeyFilter::new(&config(Some(reject_regex), &[])).unwrap();
+        KeyFilterGenerator::new(mock_returning(keys), filter, max_rerolls)
+    }
+
+    fn prefix_filtered(keys: Vec<&'static str>, deny_prefixes: &[&str], max_rerolls: usize) -> KeyFilterGenerator {
+        let filter = KeyFilter::new(&config(None, deny_prefixes)).unwrap();
+        KeyFilterGenerator::new(mock_returning(keys), filter, max_rerolls)
+    }
+
+    #[tokio::test]
+    async fn test_matching_key_is_rerolled() {
+        let generator = filtered(vec!["aaab1234", "ab123456"], "aaa", 10);
+        assert_eq!(generator.generate_key().await.unwrap(), "ab123456");
+    }
+
+    #[tokio::test]
+    async fn test_rejected_batch_keys_are_rerolled() {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_keys().with(mockall::predicate::eq(3)).times(1)
+            .returning(|_| Ok(vec!["ab000001".to_string(), "aaa00002".to_string(), "ab000003".to_string()]));
+        mock_gen.expect_generate_key().times(1).returning(|| Ok("ab000004".to_string()));
+        let filter = KeyFilter::new(&config(Some("^aaa"), &[])).unwrap();
+        let generator = KeyFilterGenerator::new(Arc::new(mock_gen), filter, 10);
+        assert_eq!(generator.generate_keys(3).await.unwrap(), vec!["ab000001", "ab000004", "ab000003"]);
+    }
+
+    #[tokio::test]
+    async fn test_clean_key_is_returned() {
+        let generator = filtered(vec!["ab123456"], "aaa", 10);
+        assert_eq!(generator.generate_key().await.unwrap(), "ab123456");
+    }
+
+    #[tokio::test]
+    async fn test_rerolls_are_capped() {
+        let generator = filtered(vec!["aaa00001", "aaa00002"], "^aaa", 1);
+        let err = generator.generate_key().await.unwrap_err();
+        assert_eq!(err, GeneratorError::RetriesExhausted("every generated key was rejected".to_string()));
+    }
+
+    #[test]
+    fn test_rerolls_exhausted_metric() {
+        let generator = filtered(vec!["aaa00001", "aaa00002"], "^aaa", 1);
+        let count = retry_exhausted_count(REASON_KEY_FILTER, async {
+            generator.generate_key().await.unwrap_err();
+        });
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_invalid_regex_fails() {
+        assert!(KeyFilter::new(&config(Some("(unclosed"), &[])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_accept_all_script() {
+        let generator = scripted(vec!["aaaaaaa1"], "fn accept(key) { true }");
+        assert_eq!(generator.generate_key().await.unwrap(), "aaaaaaa1");
+    }
+
+    #[tokio::test]
+    async fn test_rejecting_script_is_rerolled() {
+        let generator = scripted(vec!["abc00001", "xyzabc01", "xyz00002"], r#"fn accept(key) { !key.contains("abc") }"#);
+        assert_eq!(generator.generate_key().await.unwrap(), "xyz00002");
+    }
+
+    #[test]
+    fn test_script_operations_are_bounded() {
+        let script = KeyScript::compile("fn accept(key) { loop {} }", 1_000, Duration::from_secs(1)).unwrap();
+        assert!(matches!(script.accepts("aaaaaaa1"), Err(GeneratorError::UnknownError(_))));
+    }
+
+    #[test]
+    fn test_script_time_is_bounded() {
+        let script = KeyScript::compile("fn accept(key) { loop {} }", 0, Duration::from_millis(20)).unwrap();
+        let started = Instant::now();
+        assert!(matches!(script.accepts("aaaaaaa1"), Err(GeneratorError::UnknownError(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(SCRIPT_DEADLINE.get(), None);
+    }
+
+    #[test]
+    fn test_script_sizes_are_capped() {
+        let growing_string = KeyScript::compile("fn accept(key) { let s = key; loop { s += s; } }", 0, Duration::from_secs(5)).unwrap();
+        assert!(matches!(growing_string.accepts("aaaaaaa1"), Err(GeneratorError::UnknownError(_))));
+        let growing_array = KeyScript::compile("fn accept(key) { let a = []; loop { a.push(key); } }", 0, Duration::from_secs(5)).unwrap();
+        assert!(matches!(growing_array.accepts("aaaaaaa1"), Err(GeneratorError::UnknownError(_))));
+        let recursive = KeyScript::compile("fn deeper(n) { deeper(n + 1) } fn accept(key) { deeper(0) }", 0, Duration::from_secs(5)).unwrap();
+        assert!(matches!(recursive.accepts("aaaaaaa1"), Err(GeneratorError::UnknownError(_))));
+    }
+
+    #[test]
+    fn test_invalid_script_fails() {
+        assert!(KeyScript::compile("fn accept(key) {", 1_000, Duration::from_secs(1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_denied_prefix_is_rerolled() {
+        let generator = prefix_filtered(vec!["ApI12345", "wwW12345", "apx12345"], &["api", "WWW"], 10);
+        assert_eq!(generator.generate_key().await.unwrap(), "apx12345");
+    }
+
+    #[tokio::test]
+    async fn test_denied_prefix_rerolls_are_capped() {
+        let generator = prefix_filtered(vec!["admin123", "ADMIN456", "api12345"], &["admin", "api"], 2);
+        let err = generator.generate_key().await.unwrap_err();
+        assert_eq!(err, GeneratorError::RetriesExhausted("every generated key was rejected".to_string()));
+    }
+}
@@ -0,0 +1,113 @@
+//! This module defines a break-glass generator that hands out keys from a finite,
+//! operator-supplied pool loaded into memory at startup.
+//!
+//! It is meant for emergencies where the backing store is unavailable. The pool must
+//! hold keys that were never issued by the regular generator; once every key has been
+//! handed out, requests fail with `UNAVAILABLE`. Keys handed out are not persisted, so
+//! restarting the service serves the pool again from the start: regenerate the file
+//! before restarting.
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use tonic::async_trait;
+use tracing::warn;
+use crate::config::MaintenancePoolConfig;
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+
+
+/// A generator serving keys from a pre-loaded pool.
+#[derive(Debug)]
+pub struct MaintenancePoolGenerator {
+    pool: Mutex<VecDeque<String>>,
+}
+
+
+impl MaintenancePoolGenerator {
+    /// Creates a new `MaintenancePoolGenerator` from the keys of a file, one per line.
+    /// Blank lines are ignored. The size of the pool is logged, since every key served
+    /// from it is gone until the file is regenerated.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The maintenance pool configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the generator, or a `GeneratorError` if the file cannot be read
+    /// or contains duplicate keys.
+    pub fn new(config: &MaintenancePoolConfig) -> Result<Self, GeneratorError> {
+        let content = std::fs::read_to_string(&config.path)
+            .map_err(|err| GeneratorError::UnknownError(format!("Cannot read maintenance pool {}: {err}", config.path)))?;
+        let keys = content.lines().map(str::trim).filter(|line| !line.is_empty()).map(String::from).collect();
+        let generator = Self::from_keys(keys)?;
+        warn!("Serving keys from the maintenance pool {} with {} keys", config.path, generator.remaining());
+        Ok(generator)
+    }
+
+    /// Creates a new `MaintenancePoolGenerator` serving `keys` in order.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the generator, or a `GeneratorError` if a key appears twice.
+    pub fn from_keys(keys: Vec<String>) -> Result<Self, GeneratorError> {
+        let mut seen = HashSet::new();
+        if let Some(duplicate) = keys.iter().find(|key| !seen.insert(key.as_str())) {
+            return Err(GeneratorError::UnknownError(format!("Duplicate key in maintenance pool: {duplicate}")));
+        }
+        Ok(Self { pool: Mutex::new(keys.into()) })
+    }
+
+    /// Returns how many keys are left in the pool.
+    pub fn remaining(&self) -> usize {
+        self.pool.lock().unwrap().len()
+    }
+}
+
+
+#[async_trait]
+impl Generator for MaintenancePoolGenerator {
+    /// Hands out the next key of the pool.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        self.pool.lock().unwrap().pop_front().ok_or(GeneratorError::PoolExhausted)
+    }
+
+    /// Serving from the maintenance pool is always a degraded state.
+    fn is_degraded(&self) -> bool {
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_serves_pool_then_exhausts() {
+        let generator = MaintenancePoolGenerator::from_keys(keys(&["aaaaaaa1", "aaaaaaa2"])).unwrap();
+        assert_eq!(generator.generate_key().await.unwrap(), "aaaaaaa1");
+        assert_eq!(generator.generate_key().await.unwrap(), "aaaaaaa2");
+        assert_eq!(generator.remaining(), 0);
+        assert_eq!(generator.generate_key().await.unwrap_err(), GeneratorError::PoolExhausted);
+    }
+
+    #[test]
+    fn test_rejects_duplicate_keys() {
+        let err = MaintenancePoolGenerator::from_keys(keys(&["aaaaaaa1", "aaaaaaa2", "aaaaaaa1"])).unwrap_err();
+        assert_eq!(err, GeneratorError::UnknownError("Duplicate key in maintenance pool: aaaaaaa1".to_string()));
+    }
+
+    #[test]
+    fn test_loads_pool_from_file() {
+        let path = std::env::temp_dir().join(format!("maintenance-pool-{}.txt", std::process::id()));
+        std::fs::write(&path, "aaaaaaa1\n\n  aaaaaaa2  \n").unwrap();
+        let config = MaintenancePoolConfig { path: path.to_string_lossy().into_owned() };
+        let generator = MaintenancePoolGenerator::new(&config).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(generator.remaining(), 2);
+    }
+}
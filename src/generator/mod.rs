@@ -3,14 +3,33 @@
 
 use tonic::async_trait;
 use std::fmt::Debug;
+use std::sync::OnceLock;
 
 pub(crate) mod generator_object;
 mod random;
-mod redis;
+pub(crate) mod redis;
 mod primitive_root_redis;
+mod standby_redis;
+mod buffered_redis;
+mod maintenance_pool;
+mod sequence_fixed;
+mod uuid;
+mod ulid;
+mod nanoid;
+pub(crate) mod snowflake;
+pub(crate) mod region_tag;
+pub(crate) mod key_filter;
+pub(crate) mod duplicate_detection;
+pub(crate) mod window_limit;
+pub(crate) mod uniqueness;
+mod blocklist;
 pub(crate) mod error;
+pub(crate) mod encoding;
+pub(crate) mod expansion;
 
 use error::GeneratorError;
+use encoding::key_encoding;
+use expansion::KEY_LENGTH;
 
 #[cfg(test)]
 use mockall::automock;
@@ -27,30 +46,131 @@ pub trait Generator: Debug + Send + Sync {
     /// A `Result` which is either a `String` representing the generated key,
     /// or a `GeneratorError` if key generation fails.
     async fn generate_key(&self) -> Result<String, GeneratorError>;
+
+    /// Asynchronously generates `count` keys. The default calls `generate_key` once
+    /// per key; generators that can reserve keys in bulk override it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the keys, or the first `GeneratorError`.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        let mut keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            keys.push(self.generate_key().await?);
+        }
+        Ok(keys)
+    }
+
+    /// Reports the probability that at least two of `issued` keys collide.
+    ///
+    /// # Arguments
+    ///
+    /// * `issued` - The number of keys issued so far.
+    ///
+    /// # Returns
+    ///
+    /// The collision probability, or `None` if the generator cannot estimate it.
+    fn collision_probability(&self, _issued: u128) -> Option<f64> {
+        None
+    }
+
+    /// Reports whether the generator is running in a degraded state, e.g. failed over
+    /// to a standby backend.
+    fn is_degraded(&self) -> bool {
+        false
+    }
+
+    /// Asynchronously probes the backend of the generator. Generators without a backend
+    /// are always healthy.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the `GeneratorError` of the failed probe.
+    async fn health(&self) -> Result<(), GeneratorError> {
+        Ok(())
+    }
 }
 
 
 /// A trait for key generators that produce integer-based keys.
+///
+/// Keys are `u128` so that digit counts beyond what fits in a `usize`
+/// (62^11 and up on 64-bit targets) are still representable.
 #[async_trait]
 pub trait GeneratorInteger {
     /// Asynchronously generates a new integer key.
     ///
     /// # Returns
     ///
-    /// A `Result` which is either a `usize` representing the generated key,
+    /// A `Result` which is either a `u128` representing the generated key,
     /// or a `GeneratorError` if key generation fails.
-    async fn generate_key(&self) -> Result<usize, GeneratorError>;
+    async fn generate_key(&self) -> Result<u128, GeneratorError>;
+
+    /// Asynchronously generates `count` integer keys. The default calls `generate_key`
+    /// once per key.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the keys, or the first `GeneratorError`.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<u128>, GeneratorError> {
+        let mut keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            keys.push(self.generate_key().await?);
+        }
+        Ok(keys)
+    }
+
+    /// Reports the probability that at least two of `issued` keys collide.
+    /// Counter-based generators never repeat a value, so the default is `Some(0.0)`.
+    ///
+    /// # Arguments
+    ///
+    /// * `issued` - The number of keys issued so far.
+    ///
+    /// # Returns
+    ///
+    /// The collision probability, or `None` if the generator cannot estimate it.
+    fn collision_probability(&self, _issued: u128) -> Option<f64> {
+        Some(0.0)
+    }
+
+    /// Reports whether the generator is running in a degraded state, e.g. failed over
+    /// to a standby backend.
+    fn is_degraded(&self) -> bool {
+        false
+    }
+
+    /// Asynchronously probes the backend of the generator. Generators without a backend
+    /// are always healthy.
+    ///
+    /// # Returns
+    ///
+    /// An empty `Result`, or the `GeneratorError` of the failed probe.
+    async fn health(&self) -> Result<(), GeneratorError> {
+        Ok(())
+    }
 }
 
+/// The characters used to encode keys, in order of their base 62 value.
+pub const ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// The number of digits of the generated keys, read from `NUMBER_DIGITS` on first use.
+static NUMBER_DIGITS: OnceLock<usize> = OnceLock::new();
+
 /// Determines the number of digits for the generated keys based on the
-/// `NUMBER_DIGITS` environment variable.
+/// `NUMBER_DIGITS` environment variable. The variable is only read on the first call,
+/// so later changes to the environment do not change the key length.
 ///
 /// # Returns
 ///
 /// The number of digits, defaulting to 8 if the environment variable is not set or invalid.
-fn number_digits() -> usize {
-    let max_number = std::env::var("NUMBER_DIGITS").unwrap_or("8".to_string());
-    max_number.parse::<usize>().unwrap_or(8)
+pub(crate) fn number_digits() -> usize {
+    *NUMBER_DIGITS.get_or_init(|| parse_number_digits(crate::config::var("NUMBER_DIGITS").ok().as_deref()))
+}
+
+/// Parses a `NUMBER_DIGITS` value, defaulting to 8 if it is not set or invalid.
+fn parse_number_digits(value: Option<&str>) -> usize {
+    value.and_then(|value| value.parse::<usize>().ok()).unwrap_or(8)
 }
 
 
@@ -61,22 +181,53 @@ fn number_digits() -> usize {
 impl <T: GeneratorInteger + Send + Sync + Debug> Generator for T {
     async fn generate_key(&self) -> Result<String, GeneratorError> {
         let number = self.generate_key().await?;
-        Ok(convert_to_string(number))
+        convert_to_string(number)
+    }
+
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        let numbers = GeneratorInteger::generate_keys(self, count).await?;
+        numbers.into_iter().map(convert_to_string).collect()
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        GeneratorInteger::collision_probability(self, issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        GeneratorInteger::is_degraded(self)
     }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        GeneratorInteger::health(self).await
+    }
+}
+
+/// Calculates the maximum number that can be represented with the configured number of digits
+/// in the base of the key encoding, not counting a leading digit reserved for a tag.
+///
+/// # Returns
+///
+/// The maximum number as a `u128`.
+pub fn max_number() -> u128 {
+    max_number_for(number_digits().saturating_sub(KEY_LENGTH.reserved_digits()))
 }
 
-/// Calculates the maximum number that can be represented with the given number of digits
-/// in base 62.
+/// Calculates the maximum number that can be represented with `digits` digits in the base
+/// of the key encoding.
+///
+/// # Arguments
+///
+/// * `digits` - The number of digits.
 ///
 /// # Returns
 ///
-/// The maximum number as a `usize`.
-pub fn max_number() -> usize {
-    let digits = number_digits();
-    62_usize.pow(digits as u32) - 1
+/// The maximum number as a `u128`.
+pub fn max_number_for(digits: usize) -> u128 {
+    key_encoding().base().pow(digits as u32) - 1
 }
 
-/// Converts a number to a base 62 string.
+/// Converts a number to a key with the configured number of digits and encoding. With
+/// `AUTO_EXPAND`, numbers past the keyspace expand the keys instead of failing.
 ///
 /// # Arguments
 ///
@@ -84,20 +235,34 @@ pub fn max_number() -> usize {
 ///
 /// # Returns
 ///
-/// A `String` representing the number in base 62.
-pub fn convert_to_string(number: usize) -> String {
-    let mut result = String::new();
-    let mut num = number;
-    let base = 62;
-    let chars: Vec<char> = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz".chars().collect();
+/// A `Result` with the `String` representing the number in the key encoding, or a
+/// `GeneratorError::KeySpaceExhausted` if the number does not fit in the configured digits.
+pub fn convert_to_string(number: u128) -> Result<String, GeneratorError> {
+    let digits = KEY_LENGTH.digits_for(number, number_digits())?;
+    convert_to_string_with(number, digits)
+}
 
-    for _ in 0..number_digits() {
-        let remainder = num % base;
-        result.push(chars[remainder]);
-        num /= base;
+/// Converts a number to a key of exactly `digits` digits in the configured encoding.
+///
+/// Numbers above `max_number_for(digits)` are rejected rather than truncated, since
+/// dropping the most significant digits would silently produce a colliding key.
+///
+/// # Arguments
+///
+/// * `number` - The number to convert.
+/// * `digits` - The number of digits of the result.
+///
+/// # Returns
+///
+/// A `Result` with the `String` representing the number in the key encoding, or a
+/// `GeneratorError::KeySpaceExhausted` if the number does not fit in `digits` digits.
+pub fn convert_to_string_with(number: u128, digits: usize) -> Result<String, GeneratorError> {
+    let max = max_number_for(digits);
+    if number > max {
+        return Err(GeneratorError::KeySpaceExhausted { number, max });
     }
 
-    result.chars().rev().collect()
+    Ok(key_encoding().encode(number, digits))
 }
 
 
@@ -107,16 +272,53 @@ mod tests {
 
     #[tokio::test]
     async fn test_convert_to_string() {
-        assert_eq!(convert_to_string(0), "00000000");
-        assert_eq!(convert_to_string(1), "00000001");
-        assert_eq!(convert_to_string(61), "0000000z");
-        assert_eq!(convert_to_string(62), "00000010");
-        assert_eq!(convert_to_string(63), "00000011");
-        assert_eq!(convert_to_string(12345678), "0000pnfq");
+        assert_eq!(convert_to_string(0).unwrap(), "00000000");
+        assert_eq!(convert_to_string(1).unwrap(), "00000001");
+        assert_eq!(convert_to_string(61).unwrap(), "0000000z");
+        assert_eq!(convert_to_string(62).unwrap(), "00000010");
+        assert_eq!(convert_to_string(63).unwrap(), "00000011");
+        assert_eq!(convert_to_string(12345678).unwrap(), "0000pnfq");
+    }
+
+    #[tokio::test]
+    async fn test_convert_to_string_rejects_usize_max() {
+        let err = convert_to_string(usize::MAX as u128).unwrap_err();
+        assert_eq!(
+            err,
+            GeneratorError::KeySpaceExhausted { number: usize::MAX as u128, max: max_number() }
+        );
+    }
+
+    #[test]
+    fn test_convert_to_string_boundary() {
+        assert_eq!(convert_to_string(max_number()).unwrap(), "zzzzzzzz");
+        assert_eq!(
+            convert_to_string(max_number() + 1).unwrap_err(),
+            GeneratorError::KeySpaceExhausted { number: max_number() + 1, max: max_number() }
+        );
+    }
+
+    #[test]
+    fn test_number_digits_is_read_once() {
+        convert_to_string(1).unwrap();
+        assert_eq!(NUMBER_DIGITS.get(), Some(&number_digits()));
+        assert_eq!(parse_number_digits(Some("12")), 12);
+        assert_eq!(parse_number_digits(Some("twelve")), 8);
+        assert_eq!(parse_number_digits(None), 8);
     }
 
     #[tokio::test]
     async fn test_max_number() {
-        assert_eq!(max_number(), 62_usize.pow(8_u32) - 1);
+        assert_eq!(max_number(), 62_u128.pow(8_u32) - 1);
+    }
+
+    #[tokio::test]
+    async fn test_digits_beyond_usize() {
+        let max = max_number_for(12);
+        assert_eq!(max, 62_u128.pow(12_u32) - 1);
+        assert!(max > usize::MAX as u128);
+        assert_eq!(convert_to_string_with(max, 12).unwrap(), "zzzzzzzzzzzz");
+        assert_eq!(convert_to_string_with(usize::MAX as u128 + 1, 12).unwrap(), "0LygHa16AHYG");
+        assert_eq!(convert_to_string_with(62_u128.pow(11), 12).unwrap(), "100000000000");
     }
 }
@@ -0,0 +1,88 @@
+//! This module defines a key generator returning nanoids, URL-safe random IDs drawn
+//! from a configurable alphabet.
+//!
+//! Keys are `NANOID_LENGTH` characters long regardless of `NUMBER_DIGITS` and
+//! `KEY_ENCODING`. Unlike the random generator, the keyspace is not bounded by the
+//! counter keyspace: the default 21 characters over 64 symbols carry 126 random bits.
+use nanoid::nanoid;
+use tonic::async_trait;
+use crate::config::NanoidConfig;
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+use crate::generator::random::birthday_probability;
+
+
+/// A key generator returning nanoids.
+#[derive(Clone, Debug)]
+pub struct NanoidGenerator {
+    length: usize,
+    alphabet: Vec<char>,
+}
+
+
+impl NanoidGenerator {
+    /// Creates a new `NanoidGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The nanoid generator configuration.
+    pub fn new(config: &NanoidConfig) -> Self {
+        Self { length: config.length, alphabet: config.alphabet.clone() }
+    }
+
+    /// Returns the number of distinct keys, saturating at `u128::MAX`.
+    fn keyspace(&self) -> u128 {
+        (self.alphabet.len() as u128).checked_pow(self.length as u32).unwrap_or(u128::MAX)
+    }
+}
+
+
+#[async_trait]
+impl Generator for NanoidGenerator {
+    /// Generates a new nanoid of the configured length and alphabet.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        Ok(nanoid!(self.length, &self.alphabet))
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        Some(birthday_probability(issued, self.keyspace()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(length: usize, alphabet: &str) -> NanoidGenerator {
+        NanoidGenerator::new(&NanoidConfig { length, alphabet: alphabet.chars().collect() })
+    }
+
+    #[tokio::test]
+    async fn test_default_keys_are_url_safe() {
+        let generator = NanoidGenerator::new(&NanoidConfig::default());
+        let first = generator.generate_key().await.unwrap();
+        let second = generator.generate_key().await.unwrap();
+        assert_eq!(first.len(), 21);
+        assert!(first.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'));
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_keys_stay_within_the_alphabet() {
+        let generator = generator(12, "abc");
+        for _ in 0..100 {
+            let key = generator.generate_key().await.unwrap();
+            assert_eq!(key.chars().count(), 12);
+            assert!(key.chars().all(|c| "abc".contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_collision_probability() {
+        assert_eq!(generator(2, "ab").keyspace(), 4);
+        assert_eq!(generator(40, "0123456789abcdef").keyspace(), u128::MAX);
+        let default = NanoidGenerator::new(&NanoidConfig::default());
+        assert!(default.collision_probability(1_000_000_000).unwrap() < 1e-18);
+    }
+}
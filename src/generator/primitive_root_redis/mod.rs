@@ -1,5 +1,14 @@
 //! This module defines a key generator that uses a primitive root calculation
 //! combined with a Redis-based counter.
+//!
+//! The powers of a primitive root repeat with period `prime - 1`, so the generator can
+//! issue at most `prime - 1` keys, not `max_number()`. Past that the counter is rejected
+//! with `GeneratorError::OutOfRange` instead of silently reissuing keys.
+//!
+//! A root whose powers repeat sooner, or a modulus that is not prime, is rejected when
+//! the generator is created. As a second line of defence, the capacity is the actual
+//! period of the root and exponents are taken modulo that period, so even such a
+//! configuration stops before any key repeats, whatever `GENERATOR_INCREMENT_START` is.
 use std::error::Error;
 use tonic::async_trait;
 use crate::config::{PrimitiveConfig, RedisConfig};
@@ -13,6 +22,8 @@ use crate::generator::redis::RedisGenerator;
 pub struct PrimitiveRootRedisGenerator {
     pub(crate) redis_generator: RedisGenerator,
     primitive_config: PrimitiveConfig,
+    /// The number of distinct powers of the primitive root modulo the prime.
+    period: u128,
 }
 
 
@@ -29,50 +40,416 @@ impl PrimitiveRootRedisGenerator {
     ///
     /// A `Result` containing a new `PrimitiveRootRedisGenerator` or an error.
     pub fn new(config: &RedisConfig, primitive_config: &PrimitiveConfig) -> Result<Self, Box<dyn Error>> {
-        let redis_generator = RedisGenerator::new(config);
+        let redis_generator = RedisGenerator::new(config)?;
 
-        if primitive_config.prime as usize > max_number() {
+        if primitive_config.prime > max_number() {
             return Err("Generator prime is larger than max number".into());
         }
+
+        if !is_primitive_root(primitive_config.primitive_root, primitive_config.prime) {
+            return Err(format!(
+                "Generator {} is not a primitive root modulo {}",
+                primitive_config.primitive_root, primitive_config.prime
+            ).into());
+        }
         
-        Ok(
-            Self {
-                redis_generator,
-                primitive_config: primitive_config.clone(),
-            }
-        )
+        Ok(Self::with_generator(redis_generator, primitive_config))
+    }
+
+    /// Creates a `PrimitiveRootRedisGenerator` without validating the primitive root.
+    ///
+    /// # Arguments
+    ///
+    /// * `redis_generator` - The generator of the counter values.
+    /// * `primitive_config` - Configuration for the primitive root calculation.
+    pub(crate) fn with_generator(redis_generator: RedisGenerator, primitive_config: &PrimitiveConfig) -> Self {
+        let period = multiplicative_order(primitive_config.primitive_root, primitive_config.prime).unwrap_or(0);
+        Self { redis_generator, primitive_config: primitive_config.clone(), period }
+    }
+
+    /// Returns the number of keys the generator can issue before its sequence repeats:
+    /// `prime - 1` for a primitive root of a prime, and less for any other configuration.
+    pub fn capacity(&self) -> u128 {
+        self.period.min(self.primitive_config.prime - 1)
+    }
+
+    /// Checks that a counter value is within the period of the primitive root.
+    ///
+    /// # Arguments
+    ///
+    /// * `incr` - The increment value from Redis.
+    ///
+    /// # Returns
+    ///
+    /// The value, or a `GeneratorError::OutOfRange` if it is past the capacity.
+    pub fn check_period(&self, incr: u128) -> Result<u128, GeneratorError> {
+        let capacity = self.capacity();
+        if incr > capacity {
+            return Err(GeneratorError::OutOfRange { counter: incr, capacity });
+        }
+        Ok(incr)
     }
 
     /// Calculate the key using the formula: `key = (primitive_root ^ (incr + incr_start)) % prime`.
     ///
+    /// The exponent is reduced modulo the period of the root, not the prime, so any
+    /// `period` consecutive counter values give distinct keys whatever the start.
+    ///
     /// # Arguments
     ///
     /// * `incr` - The increment value from Redis.
     ///
     /// # Returns
     ///
-    /// The calculated key as a `usize`.
-    pub fn calculate_key(&self, incr: usize) -> usize {
-        let mut result = 1;
-        let mut base = self.primitive_config.primitive_root;
-        let mut exponent = (incr as u128 + self.primitive_config.start) % self.primitive_config.prime;
-        
-        while exponent > 0 {
-            if exponent % 2 == 1 {
-                result = (result * base) % self.primitive_config.prime;
+    /// The calculated key as a `u128`.
+    pub fn calculate_key(&self, incr: u128) -> u128 {
+        let period = self.period.max(1);
+        let exponent = add_mod(incr % period, self.primitive_config.start % period, period);
+        pow_mod(self.primitive_config.primitive_root, exponent, self.primitive_config.prime)
+    }
+}
+
+
+/// Computes `(a + b) % modulus` for `a, b < modulus` without overflowing.
+fn add_mod(a: u128, b: u128, modulus: u128) -> u128 {
+    if a >= modulus - b { a - (modulus - b) } else { a + b }
+}
+
+
+/// Computes `(a * b) % modulus` for any `modulus > 0` without overflowing.
+///
+/// The product is computed directly when it fits in a `u128` and by
+/// double-and-add otherwise, e.g. for a prime near `u64::MAX` or larger.
+pub fn mul_mod(a: u128, b: u128, modulus: u128) -> u128 {
+    let (mut a, mut b) = (a % modulus, b % modulus);
+    if let Some(product) = a.checked_mul(b) {
+        return product % modulus;
+    }
+    let mut result = 0;
+    while b > 0 {
+        if b & 1 == 1 {
+            result = add_mod(result, a, modulus);
+        }
+        a = add_mod(a, a, modulus);
+        b >>= 1;
+    }
+    result
+}
+
+
+/// Computes `base ^ exponent % modulus` by square-and-multiply.
+pub fn pow_mod(base: u128, exponent: u128, modulus: u128) -> u128 {
+    let mut result = 1 % modulus;
+    let mut base = base % modulus;
+    let mut exponent = exponent;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Checks that `root` has multiplicative order `prime - 1` modulo `prime`, so its powers
+/// run through every nonzero residue before repeating.
+///
+/// The order is `prime - 1` if `root ^ (prime - 1)` is 1 but no `root ^ ((prime - 1) / q)`
+/// is, for the prime factors `q` of `prime - 1`. Only a prime modulus has an element of
+/// that order, so a composite `prime` is rejected as well.
+pub fn is_primitive_root(root: u128, prime: u128) -> bool {
+    prime >= 2 && multiplicative_order(root, prime) == Some(prime - 1)
+}
+
+
+/// Returns the multiplicative order of `root` modulo `modulus`, the smallest `d > 0` with
+/// `root ^ d % modulus == 1`, which is the number of distinct powers before they repeat.
+///
+/// The order divides Euler's totient of the modulus, which is `modulus - 1` only for a
+/// prime, so it is found by dividing the prime factors out of the totient while the
+/// power stays 1.
+///
+/// # Returns
+///
+/// The order, or `None` if `root` shares a factor with `modulus`, whose powers then never
+/// return to 1.
+pub fn multiplicative_order(root: u128, modulus: u128) -> Option<u128> {
+    if modulus < 2 || gcd(root % modulus, modulus) != 1 {
+        return None;
+    }
+    let totient = prime_factors(modulus)
+        .into_iter()
+        .fold(modulus, |totient, factor| totient / factor * (factor - 1));
+    let mut order = totient;
+    for factor in prime_factors(totient) {
+        while order % factor == 0 && pow_mod(root, order / factor, modulus) == 1 {
+            order /= factor;
+        }
+    }
+    Some(order)
+}
+
+
+/// Returns the distinct prime factors of `n` in increasing order, by trial division of
+/// the small factors and Pollard's rho for the rest.
+pub fn prime_factors(mut n: u128) -> Vec<u128> {
+    let mut factors = Vec::new();
+    let mut divisor = 2;
+    while divisor < 1000 && divisor * divisor <= n {
+        if n % divisor == 0 {
+            factors.push(divisor);
+            while n % divisor == 0 {
+                n /= divisor;
             }
-            base = (base * base) % self.primitive_config.prime;
-            exponent /= 2;
         }
-        result as usize
+        divisor += 1;
+    }
+    let mut pending = vec![n];
+    while let Some(n) = pending.pop() {
+        if n == 1 {
+            continue;
+        }
+        if is_probable_prime(n) {
+            factors.push(n);
+            continue;
+        }
+        let divisor = pollard_rho(n);
+        pending.push(divisor);
+        pending.push(n / divisor);
     }
+    factors.sort_unstable();
+    factors.dedup();
+    factors
+}
+
+
+/// The Miller-Rabin bases of `is_probable_prime`.
+const MILLER_RABIN_BASES: [u128; 13] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+
+
+/// Checks whether `n` is prime with the Miller-Rabin test. The answer is exact below
+/// 3.3 * 10^24 and a composite above passes with negligible probability.
+pub fn is_probable_prime(n: u128) -> bool {
+    if n < 2 {
+        return false;
+    }
+    if let Some(base) = MILLER_RABIN_BASES.iter().find(|base| n % *base == 0) {
+        return n == *base;
+    }
+    let (mut odd, mut twos) = (n - 1, 0);
+    while odd % 2 == 0 {
+        odd /= 2;
+        twos += 1;
+    }
+    'bases: for base in MILLER_RABIN_BASES {
+        let mut x = pow_mod(base, odd, n);
+        if x == 1 || x == n - 1 {
+            continue;
+        }
+        for _ in 1..twos {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                continue 'bases;
+            }
+        }
+        return false;
+    }
+    true
+}
+
+
+/// Finds a nontrivial divisor of the odd composite `n` with Pollard's rho.
+fn pollard_rho(n: u128) -> u128 {
+    let mut increment = 1;
+    loop {
+        let step = |x| add_mod(mul_mod(x, x, n), increment, n);
+        let (mut slow, mut fast, mut divisor) = (2, 2, 1);
+        while divisor == 1 {
+            slow = step(slow);
+            fast = step(step(fast));
+            divisor = gcd(slow.abs_diff(fast), n);
+        }
+        if divisor != n {
+            return divisor;
+        }
+        increment += 1;
+    }
+}
+
+
+/// Computes the greatest common divisor of `a` and `b`.
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
 }
 
 /// Generate a key using the generator.
 #[async_trait]
 impl GeneratorInteger for PrimitiveRootRedisGenerator {
-    async fn generate_key(&self) -> Result<usize, GeneratorError> {
+    async fn generate_key(&self) -> Result<u128, GeneratorError> {
         let key = self.redis_generator.generate_key().await?;
-        Ok(self.calculate_key(key))
+        Ok(self.calculate_key(self.check_period(key)?))
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.redis_generator.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RetryConfig;
+
+    fn redis_config() -> RedisConfig {
+        RedisConfig {
+            url: "redis://localhost:6379".to_string(),
+            monotonic_guard: true,
+            pool_size: 1,
+            loss_threshold: None,
+            high_water_file: None,
+            retry: RetryConfig::default(),
+            coalesce: None,
+        }
+    }
+
+    fn generator(prime: u128, start: u128) -> PrimitiveRootRedisGenerator {
+        let primitive_config = PrimitiveConfig { prime, start, primitive_root: 2 };
+        PrimitiveRootRedisGenerator::new(&redis_config(), &primitive_config).unwrap()
+    }
+
+    #[test]
+    fn test_counter_within_period() {
+        let generator = generator(11, 0);
+        assert_eq!(generator.capacity(), 10);
+        let keys: Vec<u128> = (1..=10).map(|incr| generator.calculate_key(generator.check_period(incr).unwrap())).collect();
+        let mut unique = keys.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 10);
+    }
+
+    /// The largest prime below 2^64.
+    const LARGE_PRIME: u128 = 18446744073709551557;
+
+    #[test]
+    fn test_pow_mod_large_prime() {
+        // Reference values computed with arbitrary-precision integers.
+        assert_eq!(pow_mod(2, LARGE_PRIME - 1, LARGE_PRIME), 1);
+        assert_eq!(pow_mod(2, (LARGE_PRIME - 1) / 2, LARGE_PRIME), LARGE_PRIME - 1);
+        assert_eq!(pow_mod(2, 12345678901234567890, LARGE_PRIME), 9888492272568970702);
+        assert_eq!(pow_mod(3, 1_000_000_000_000_000_000, LARGE_PRIME), 4014180641660839766);
+        assert_eq!(pow_mod(3, 10_u128.pow(30), (1 << 127) - 1), 154529045331661267443158746728834222196);
+    }
+
+    #[test]
+    fn test_mul_mod_does_not_overflow() {
+        let modulus = u128::MAX - 158;
+        assert_eq!(mul_mod(modulus - 41, modulus - 141, modulus), 5781);
+        assert_eq!(mul_mod(LARGE_PRIME - 1, LARGE_PRIME - 1, LARGE_PRIME), 1);
+        assert_eq!(mul_mod(6, 7, 10), 2);
+    }
+
+    #[test]
+    fn test_calculate_key_large_prime() {
+        let primitive_config = PrimitiveConfig { prime: LARGE_PRIME, start: 1_000_000_000_000_000_000 - 5, primitive_root: 2 };
+        let generator = PrimitiveRootRedisGenerator::with_generator(generator(11, 0).redis_generator, &primitive_config);
+        assert_eq!(generator.calculate_key(5), 15194517888737919093);
+    }
+
+    #[test]
+    fn test_prime_factors() {
+        assert_eq!(prime_factors(1000002), vec![2, 3, 166667]);
+        assert_eq!(prime_factors(LARGE_PRIME - 1), vec![2, 11, 137, 547, 5594472617641]);
+        assert_eq!(prime_factors(1000001 * 1000003), vec![101, 9901, 1000003]);
+        assert!(is_probable_prime(LARGE_PRIME));
+        assert!(!is_probable_prime(1000001));
+    }
+
+    #[test]
+    fn test_primitive_root_order() {
+        assert!(is_primitive_root(2, 11));
+        assert!(is_primitive_root(2, 1000003));
+        assert!(is_primitive_root(2, LARGE_PRIME));
+        // 3 has order 5 modulo 11, and a square is never a primitive root.
+        assert!(!is_primitive_root(3, 11));
+        assert!(!is_primitive_root(4, LARGE_PRIME));
+        // No element of a composite modulus has order modulus - 1.
+        assert!(!is_primitive_root(2, 1000001));
+    }
+
+    #[test]
+    fn test_multiplicative_order() {
+        assert_eq!(multiplicative_order(2, 11), Some(10));
+        assert_eq!(multiplicative_order(3, 11), Some(5));
+        assert_eq!(multiplicative_order(2, 21), Some(6));
+        assert_eq!(multiplicative_order(2, LARGE_PRIME), Some(LARGE_PRIME - 1));
+        assert_eq!(multiplicative_order(7, 21), None);
+    }
+
+    #[test]
+    fn test_composite_modulus_stops_before_a_repeat() {
+        // 21 is not prime and the powers of 2 modulo 21 repeat every 6 keys.
+        let primitive_config = PrimitiveConfig { prime: 21, start: 0, primitive_root: 2 };
+        let generator = PrimitiveRootRedisGenerator::with_generator(generator(11, 0).redis_generator, &primitive_config);
+        assert_eq!(generator.capacity(), 6);
+        let mut keys: Vec<u128> = (1..=6).map(|incr| generator.calculate_key(generator.check_period(incr).unwrap())).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 6);
+        assert_eq!(generator.calculate_key(7), generator.calculate_key(1));
+        assert_eq!(generator.check_period(7), Err(GeneratorError::OutOfRange { counter: 7, capacity: 6 }));
+    }
+
+    #[test]
+    fn test_composite_modulus_with_start_stops_before_a_repeat() {
+        for start in [1, 4, 15, 20, 1000] {
+            let primitive_config = PrimitiveConfig { prime: 21, start, primitive_root: 2 };
+            let generator = PrimitiveRootRedisGenerator::with_generator(generator(11, 0).redis_generator, &primitive_config);
+            assert_eq!(generator.capacity(), 6);
+            assert_eq!(distinct_keys(&generator), 6, "start {start}");
+        }
+    }
+
+    #[test]
+    fn test_non_primitive_root_is_rejected() {
+        let primitive_config = PrimitiveConfig { prime: 11, start: 0, primitive_root: 3 };
+        let err = PrimitiveRootRedisGenerator::new(&redis_config(), &primitive_config).unwrap_err();
+        assert_eq!(err.to_string(), "Generator 3 is not a primitive root modulo 11");
+    }
+
+    fn distinct_keys(generator: &PrimitiveRootRedisGenerator) -> usize {
+        let mut keys: Vec<u128> = (1..=generator.capacity())
+            .map(|incr| generator.calculate_key(generator.check_period(incr).unwrap()))
+            .collect();
+        keys.sort();
+        keys.dedup();
+        keys.len()
+    }
+
+    #[test]
+    fn test_nonzero_start_does_not_repeat_keys() {
+        for start in [1, 5, 6, 7, 100] {
+            let primitive_config = PrimitiveConfig { prime: 7, start, primitive_root: 3 };
+            let generator = PrimitiveRootRedisGenerator::with_generator(generator(11, 0).redis_generator, &primitive_config);
+            assert_eq!(generator.capacity(), 6);
+            assert_eq!(distinct_keys(&generator), 6, "start {start}");
+        }
+        let generator = generator(11, 3);
+        assert_eq!(distinct_keys(&generator), 10);
+        assert_eq!(generator.calculate_key(1), pow_mod(2, 4, 11));
+    }
+
+    #[test]
+    fn test_counter_past_period() {
+        let generator = generator(11, 0);
+        // Without the check, the sequence wraps and reissues the first key.
+        assert_eq!(generator.calculate_key(12), generator.calculate_key(1));
+        assert_eq!(generator.check_period(11), Err(GeneratorError::OutOfRange { counter: 11, capacity: 10 }));
+        assert_eq!(generator.check_period(21), Err(GeneratorError::OutOfRange { counter: 21, capacity: 10 }));
     }
 }
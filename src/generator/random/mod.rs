@@ -1,19 +1,119 @@
 //! This module defines a key generator that produces random numbers.
-use rand::Rng;
+//!
+//! With `RANDOM_DEDUP_WINDOW` set, the generator remembers the values it issued most
+//! recently and re-rolls a draw repeating one of them. This is a best-effort local
+//! mitigation: values issued before the window, by other instances or before a restart
+//! can still repeat.
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use tonic::async_trait;
+use crate::config::RandomConfig;
 use crate::generator::{max_number, GeneratorInteger};
+use crate::generator::blocklist::{Blocklist, RedisBlocklist};
 use crate::generator::error::GeneratorError;
+use crate::instrumentation::{record_retry_exhausted, REASON_BLOCKLIST, REASON_DEDUP};
 
 /// A key generator that produces random numbers.
-#[derive(Clone, Debug)]
-pub struct RandomGenerator;
+#[derive(Debug)]
+pub struct RandomGenerator {
+    /// The largest value drawn, `max_number()` when the generator was created.
+    max: u128,
+    /// The values that must never be emitted, if a blocklist is configured.
+    blocklist: Option<Arc<dyn Blocklist>>,
+    /// How many times a blocked or recently issued value is re-rolled before giving up.
+    max_rerolls: usize,
+    /// The values issued most recently, if a dedup window is configured.
+    recent: Option<Mutex<RecentValues>>,
+    /// The random number generator, or `None` to use the thread RNG.
+    rng: Option<Mutex<StdRng>>,
+}
+
+
+/// The last `capacity` values issued, evicted oldest first.
+///
+/// A remembered value is never issued again while it is in the window, so eviction in
+/// issuance order also evicts the least recently used value.
+#[derive(Debug)]
+struct RecentValues {
+    capacity: usize,
+    order: VecDeque<u128>,
+    values: HashSet<u128>,
+}
 
 
+impl RecentValues {
+    /// Creates an empty window of `capacity` values.
+    fn new(capacity: usize) -> Self {
+        Self { capacity, order: VecDeque::with_capacity(capacity), values: HashSet::with_capacity(capacity) }
+    }
+
+    /// Remembers `value`, evicting the oldest value if the window is full.
+    ///
+    /// # Returns
+    ///
+    /// `false` if `value` is already in the window, in which case nothing changes.
+    fn insert(&mut self, value: u128) -> bool {
+        if self.values.contains(&value) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.values.remove(&oldest);
+            }
+        }
+        self.order.push_back(value);
+        self.values.insert(value);
+        true
+    }
+}
+
 
 impl RandomGenerator {
     /// Creates a new `RandomGenerator`.
-    pub fn new() -> Self {
-        Self {}
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The random generator configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a new `RandomGenerator`, or a `GeneratorError` if the
+    /// blocklist cannot be set up.
+    pub fn new(config: &RandomConfig) -> Result<Self, GeneratorError> {
+        let blocklist = match &config.blocklist {
+            Some(redis_config) => Some(Arc::new(RedisBlocklist::new(redis_config)?) as Arc<dyn Blocklist>),
+            None => None,
+        };
+        let recent = (config.dedup_window > 0).then(|| Mutex::new(RecentValues::new(config.dedup_window)));
+        Ok(Self {
+            max: max_number(),
+            blocklist,
+            max_rerolls: config.max_rerolls,
+            recent,
+            rng: config.seed.map(|seed| Mutex::new(StdRng::seed_from_u64(seed))),
+        })
+    }
+
+    /// Draws a random number within the allowed range.
+    fn draw(&self) -> u128 {
+        match &self.rng {
+            Some(rng) => rng.lock().unwrap().random_range(0..=self.max),
+            None => rand::rng().random_range(0..=self.max),
+        }
+    }
+
+    /// Remembers `value` as issued if a dedup window is configured.
+    ///
+    /// # Returns
+    ///
+    /// `false` if `value` was issued recently and must be re-rolled.
+    fn remember(&self, value: u128) -> bool {
+        match &self.recent {
+            Some(recent) => recent.lock().unwrap().insert(value),
+            None => true,
+        }
     }
 }
 
@@ -24,9 +124,231 @@ impl GeneratorInteger for RandomGenerator {
     ///
     /// # Returns
     ///
-    /// A `Result` containing a random `usize` or a `GeneratorError`.
-    async fn generate_key(&self) -> Result<usize, GeneratorError> {
-        let mut rng = rand::rng();
-        Ok(rng.random_range(0..=max_number()))
+    /// A `Result` containing a random `u128` or a `GeneratorError`.
+    async fn generate_key(&self) -> Result<u128, GeneratorError> {
+        if self.blocklist.is_none() && self.recent.is_none() {
+            return Ok(self.draw());
+        }
+        let mut reason = REASON_BLOCKLIST;
+        for _ in 0..=self.max_rerolls {
+            let value = self.draw();
+            if let Some(blocklist) = &self.blocklist {
+                if blocklist.contains(value).await? {
+                    reason = REASON_BLOCKLIST;
+                    continue;
+                }
+            }
+            if self.remember(value) {
+                return Ok(value);
+            }
+            reason = REASON_DEDUP;
+        }
+        record_retry_exhausted(reason);
+        if reason == REASON_DEDUP {
+            return Err(GeneratorError::UnknownError("every drawn value was issued recently".to_string()));
+        }
+        Err(GeneratorError::RetriesExhausted("every drawn value was blocked".to_string()))
+    }
+
+    /// Approximates the collision probability with the birthday bound over the whole keyspace.
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        Some(birthday_probability(issued, self.max + 1))
+    }
+}
+
+
+/// Approximates the probability that at least two of `issued` uniformly drawn values
+/// out of `keyspace` collide, using `1 - exp(-n(n-1) / 2N)`.
+///
+/// # Arguments
+///
+/// * `issued` - The number of drawn values.
+/// * `keyspace` - The number of possible values.
+///
+/// # Returns
+///
+/// The collision probability between 0 and 1.
+pub fn birthday_probability(issued: u128, keyspace: u128) -> f64 {
+    let n = issued as f64;
+    let exponent = n * (n - 1.0).max(0.0) / (2.0 * keyspace as f64);
+    -(-exponent).exp_m1()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::blocklist::MockBlocklist;
+    use crate::instrumentation::retry_exhausted_count;
+
+    fn with_blocklist(blocklist: MockBlocklist, max_rerolls: usize) -> RandomGenerator {
+        RandomGenerator {
+            max: max_number(),
+            blocklist: Some(Arc::new(blocklist)),
+            max_rerolls,
+            recent: None,
+            rng: None,
+        }
+    }
+
+    fn with_dedup_window(seed: u64, window: usize, max_rerolls: usize) -> RandomGenerator {
+        RandomGenerator {
+            max: max_number(),
+            blocklist: None,
+            max_rerolls,
+            recent: Some(Mutex::new(RecentValues::new(window))),
+            rng: Some(Mutex::new(StdRng::seed_from_u64(seed))),
+        }
+    }
+
+    fn draws(seed: u64, count: usize) -> Vec<u128> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count).map(|_| rng.random_range(0..=max_number())).collect()
+    }
+
+    #[tokio::test]
+    async fn test_same_seed_gives_the_same_sequence() {
+        let config = RandomConfig { seed: Some(42), ..RandomConfig::default() };
+        let first = RandomGenerator::new(&config).unwrap();
+        let second = RandomGenerator::new(&config).unwrap();
+        let mut keys = Vec::new();
+        for _ in 0..20 {
+            let key = GeneratorInteger::generate_key(&first).await.unwrap();
+            assert_eq!(GeneratorInteger::generate_key(&second).await, Ok(key));
+            keys.push(key);
+        }
+        assert_eq!(keys, draws(42, 20));
+    }
+
+    #[test]
+    fn test_recent_values_evict_the_oldest() {
+        let mut recent = RecentValues::new(2);
+        assert!(recent.insert(1));
+        assert!(recent.insert(2));
+        assert!(!recent.insert(1));
+        assert!(recent.insert(3));
+        assert!(recent.insert(1));
+        assert!(!recent.insert(3));
+    }
+
+    #[tokio::test]
+    async fn test_recent_collision_is_rerolled_once() {
+        let expected = draws(42, 2);
+        let generator = with_dedup_window(42, 10, 3);
+        generator.remember(expected[0]);
+        assert_eq!(GeneratorInteger::generate_key(&generator).await, Ok(expected[1]));
+        assert!(!generator.remember(expected[1]));
+    }
+
+    #[test]
+    fn test_recent_collisions_exhausted() {
+        let generator = with_dedup_window(42, 10, 0);
+        generator.remember(draws(42, 1)[0]);
+        let count = retry_exhausted_count(REASON_DEDUP, async {
+            let err = GeneratorInteger::generate_key(&generator).await.unwrap_err();
+            assert_eq!(err, GeneratorError::UnknownError("every drawn value was issued recently".to_string()));
+        });
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_values_are_rerolled() {
+        let checked = Arc::new(Mutex::new(Vec::new()));
+        let seen = checked.clone();
+        let mut blocklist = MockBlocklist::new();
+        blocklist.expect_contains().times(3).returning(move |value| {
+            let mut seen = seen.lock().unwrap();
+            seen.push(value);
+            Ok(seen.len() < 3)
+        });
+        let generator = with_blocklist(blocklist, 5);
+        let value = GeneratorInteger::generate_key(&generator).await.unwrap();
+        let checked = checked.lock().unwrap();
+        assert_eq!(value, checked[2]);
+    }
+
+    #[tokio::test]
+    async fn test_blocked_value_never_emitted() {
+        let mut blocklist = MockBlocklist::new();
+        blocklist.expect_contains().returning(|value| Ok(value % 2 == 0));
+        let generator = with_blocklist(blocklist, 100);
+        for _ in 0..50 {
+            let value = GeneratorInteger::generate_key(&generator).await.unwrap();
+            assert_eq!(value % 2, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rerolls_exhausted() {
+        let mut blocklist = MockBlocklist::new();
+        blocklist.expect_contains().times(4).returning(|_| Ok(true));
+        let generator = with_blocklist(blocklist, 3);
+        let err = GeneratorInteger::generate_key(&generator).await.unwrap_err();
+        assert_eq!(err, GeneratorError::RetriesExhausted("every drawn value was blocked".to_string()));
+    }
+
+    #[test]
+    fn test_rerolls_exhausted_metric() {
+        let mut blocklist = MockBlocklist::new();
+        blocklist.expect_contains().returning(|_| Ok(true));
+        let generator = with_blocklist(blocklist, 3);
+        let count = retry_exhausted_count(REASON_BLOCKLIST, async {
+            GeneratorInteger::generate_key(&generator).await.unwrap_err();
+        });
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_region_tag_keeps_every_drawn_digit() {
+        use crate::generator::{max_number_for, number_digits, Generator};
+        use crate::generator::region_tag::RegionTagGenerator;
+
+        // At startup, REGION_TAG reserves the leading digit and the draws shrink to the others.
+        let config = RandomConfig { seed: Some(42), ..RandomConfig::default() };
+        let generator = RandomGenerator { max: max_number_for(number_digits() - 1), ..RandomGenerator::new(&config).unwrap() };
+        let tagged = RegionTagGenerator::new(Arc::new(generator), 'E');
+        for _ in 0..200 {
+            let key = tagged.generate_key().await.unwrap();
+            assert!(key.starts_with('E'));
+            assert_eq!(key.len(), number_digits());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheme_prefixed_keys_decode() {
+        use crate::config::GeneratorConfig;
+        use crate::generator::{max_number_for, number_digits, Generator};
+        use crate::generator::encoding::key_encoding;
+        use crate::generator::region_tag::RegionTagGenerator;
+
+        let config = RandomConfig { seed: Some(7), ..RandomConfig::default() };
+        let prefix = GeneratorConfig::Random(config.clone()).scheme_prefix().unwrap();
+        let max = max_number_for(number_digits() - 1);
+        let generator = RandomGenerator { max, ..RandomGenerator::new(&config).unwrap() };
+        let prefixed = RegionTagGenerator::new(Arc::new(generator), prefix);
+        for _ in 0..200 {
+            let key = prefixed.generate_key().await.unwrap();
+            assert!(key.starts_with('R'));
+            assert!(key_encoding().decode(&key[1..]).unwrap() <= max);
+        }
+    }
+
+    #[test]
+    fn test_birthday_probability() {
+        assert_eq!(birthday_probability(0, 365), 0.0);
+        assert_eq!(birthday_probability(1, 365), 0.0);
+        assert!((birthday_probability(23, 365) - 0.5).abs() < 0.01);
+        assert!(birthday_probability(200, 365) > 0.99);
+    }
+
+    #[test]
+    fn test_random_collision_probability() {
+        let generator = RandomGenerator::new(&RandomConfig::default()).unwrap();
+        let keyspace = (max_number() + 1) as f64;
+        let low = GeneratorInteger::collision_probability(&generator, 1_000).unwrap();
+        let high = GeneratorInteger::collision_probability(&generator, 100_000_000).unwrap();
+        assert!((low - 1_000.0 * 999.0 / (2.0 * keyspace)).abs() < 1e-12);
+        assert!(low < high);
+        assert!(high > 0.9);
     }
 }
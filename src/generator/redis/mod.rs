@@ -1,32 +1,522 @@
 //! This module defines a Redis-based key generator that increments a counter
 //! in Redis to produce unique keys.
+//!
+//! Under `REDIS_COALESCE`, increments arriving within a short window are merged into
+//! one `INCRBY` and the reserved range is split among the waiting callers, trading a
+//! little latency for far fewer Redis commands under load.
 
-use std::sync::Arc;
-use redis::Client;
+use std::fmt::Debug;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use deadpool_redis::{Connection, Pool, PoolConfig, PoolError, Runtime};
+use redis::{ErrorKind, RedisError};
+use tokio::sync::oneshot;
 use tonic::async_trait;
-use crate::config::RedisConfig;
+use tracing::warn;
+use crate::config::{CoalesceConfig, RedisConfig, RetryConfig};
 use crate::generator::error::GeneratorError;
 use crate::generator::GeneratorInteger;
 
+#[cfg(test)]
+use mockall::automock;
+
+/// The Redis key holding the shared counter.
+pub(crate) const COUNTER_KEY: &str = "incr:count";
+
+/// Lua script that raises the counter to `ARGV[1]` unless it is already higher.
+const RAISE_COUNTER_SCRIPT: &str = r"
+local current = tonumber(redis.call('GET', KEYS[1]) or '0')
+if current < tonumber(ARGV[1]) then
+    redis.call('SET', KEYS[1], ARGV[1])
+    return ARGV[1]
+end
+return tostring(current)
+";
+
+/// `CounterGuard` remembers the highest counter value this process has seen and
+/// rejects any value that is not higher, which protects against a failover to a
+/// replica that lags behind and would make `INCR` reissue keys.
+///
+/// Values are compared with the high-water mark read *before* their `INCR` was sent,
+/// not with the one at the time they are checked: concurrent increments of one process
+/// can complete in any order, but a healthy counter always returns a value above every
+/// value returned before the command was sent.
+///
+/// A value more than `loss_threshold` below the highest one is not a lagging replica
+/// but a counter that was lost, e.g. evicted under memory pressure, and restarted from 1.
+#[derive(Debug, Default)]
+pub struct CounterGuard {
+    high_water: Mutex<u128>,
+    loss_threshold: Option<u128>,
+}
+
+
+impl CounterGuard {
+    /// Creates a new `CounterGuard`.
+    ///
+    /// # Arguments
+    ///
+    /// * `high_water` - The highest counter value known to have been issued.
+    /// * `loss_threshold` - How far below `high_water` a value means the counter was lost, if detected.
+    pub fn new(high_water: u128, loss_threshold: Option<u128>) -> Self {
+        Self { high_water: Mutex::new(high_water), loss_threshold }
+    }
+
+    /// Returns the highest counter value seen so far.
+    pub fn high_water(&self) -> u128 {
+        *self.high_water.lock().unwrap()
+    }
+
+    /// Checks a counter value returned by `INCR` and raises the high-water mark to it.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The counter value returned by Redis.
+    /// * `floor` - The high-water mark read before the `INCR` was sent.
+    ///
+    /// # Returns
+    ///
+    /// The value if it is higher than `floor`, otherwise a `GeneratorError::CounterLost`
+    /// if it is more than the loss threshold below, or a `GeneratorError::StaleCounter`.
+    pub fn check(&self, value: u128, floor: u128) -> Result<u128, GeneratorError> {
+        if value <= floor {
+            if self.loss_threshold.is_some_and(|threshold| value.saturating_add(threshold) < floor) {
+                return Err(GeneratorError::CounterLost { observed: value, last_seen: floor });
+            }
+            return Err(GeneratorError::StaleCounter { observed: value, last_seen: floor });
+        }
+        let mut high_water = self.high_water.lock().unwrap();
+        *high_water = (*high_water).max(value);
+        Ok(value)
+    }
+}
+
+
+/// Reads the high-water mark persisted in `path`.
+///
+/// # Returns
+///
+/// The persisted value, 0 if the file does not exist yet, or an error if it cannot
+/// be read or does not hold a number.
+pub fn load_high_water(path: &str) -> io::Result<u128> {
+    match std::fs::read_to_string(path) {
+        Ok(value) => value.trim().parse::<u128>().map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+
+/// Persists a high-water mark in `path`, replacing the file atomically.
+pub fn store_high_water(path: &str, value: u128) -> io::Result<()> {
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, value.to_string())?;
+    std::fs::rename(tmp, path)
+}
+
+
+/// `CounterStore` is the asynchronous storage of the shared counter.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait CounterStore: Debug + Send + Sync {
+    /// Increments the counter and returns its new value.
+    async fn incr(&self) -> Result<String, GeneratorError>;
+
+    /// Increments the counter by `count` and returns its new value.
+    async fn incr_by(&self, count: u64) -> Result<String, GeneratorError>;
+
+    /// Returns the current value of the counter, if it was ever set.
+    async fn get(&self) -> Result<Option<String>, GeneratorError>;
+
+    /// Raises the counter to `value` unless it is already higher and returns its value.
+    async fn raise_to(&self, value: u128) -> Result<String, GeneratorError>;
+
+    /// Checks that the store answers.
+    async fn ping(&self) -> Result<(), GeneratorError>;
+}
+
+
+/// `RedisStore` keeps the counter in Redis, borrowing a connection from a pool for
+/// every command.
+///
+/// Connections are opened on demand up to the pool size, so creating the store never
+/// blocks. When every connection is in use, callers wait for one to be returned.
+#[derive(Debug)]
+pub struct RedisStore {
+    pool: Pool,
+}
+
+
+impl RedisStore {
+    /// Creates a new `RedisStore`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Redis configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the store, or a `GeneratorError::InvalidConfig` if the URL is invalid.
+    pub fn new(config: &RedisConfig) -> Result<Self, GeneratorError> {
+        Ok(Self { pool: redis_pool(config)? })
+    }
+
+    /// Borrows a connection from the pool until it is dropped.
+    async fn connection(&self) -> Result<Connection, GeneratorError> {
+        pooled_connection(&self.pool).await
+    }
+}
+
+
+#[async_trait]
+impl CounterStore for RedisStore {
+    async fn incr(&self) -> Result<String, GeneratorError> {
+        redis::cmd("INCR")
+            .arg(COUNTER_KEY)
+            .query_async(&mut self.connection().await?)
+            .await
+            .map_err(map_redis_error)
+    }
+
+    async fn incr_by(&self, count: u64) -> Result<String, GeneratorError> {
+        redis::cmd("INCRBY")
+            .arg(COUNTER_KEY)
+            .arg(count)
+            .query_async(&mut self.connection().await?)
+            .await
+            .map_err(map_redis_error)
+    }
+
+    async fn get(&self) -> Result<Option<String>, GeneratorError> {
+        redis::cmd("GET")
+            .arg(COUNTER_KEY)
+            .query_async(&mut self.connection().await?)
+            .await
+            .map_err(map_redis_error)
+    }
+
+    async fn raise_to(&self, value: u128) -> Result<String, GeneratorError> {
+        redis::Script::new(RAISE_COUNTER_SCRIPT)
+            .key(COUNTER_KEY)
+            .arg(value.to_string())
+            .invoke_async(&mut self.connection().await?)
+            .await
+            .map_err(map_redis_error)
+    }
+
+    async fn ping(&self) -> Result<(), GeneratorError> {
+        redis::cmd("PING")
+            .query_async::<String>(&mut self.connection().await?)
+            .await
+            .map(|_| ())
+            .map_err(map_redis_error)
+    }
+}
+
+
+/// A caller waiting for its key from a coalesced `INCRBY`.
+type Waiter = oneshot::Sender<Result<u128, GeneratorError>>;
+
+
+/// `Coalescer` collects the increments waiting to be merged into the next `INCRBY`.
+#[derive(Debug)]
+struct Coalescer {
+    config: CoalesceConfig,
+    batch: Mutex<PendingBatch>,
+}
+
+
+/// The open batch of a `Coalescer`.
+#[derive(Debug, Default)]
+struct PendingBatch {
+    /// Counts the batches taken so far, so the timer of a batch never takes a later one.
+    id: u64,
+    waiters: Vec<Waiter>,
+}
+
+
+impl Coalescer {
+    fn new(config: CoalesceConfig) -> Self {
+        Self { config, batch: Mutex::new(PendingBatch::default()) }
+    }
+
+    /// Adds `waiter` to the open batch.
+    ///
+    /// # Returns
+    ///
+    /// The id of the batch if `waiter` opened it, and the batch if it is now full.
+    fn join(&self, waiter: Waiter) -> (Option<u64>, Option<Vec<Waiter>>) {
+        let mut batch = self.batch.lock().unwrap();
+        batch.waiters.push(waiter);
+        let opened = (batch.waiters.len() == 1).then_some(batch.id);
+        let full = (batch.waiters.len() >= self.config.max_batch).then(|| {
+            batch.id += 1;
+            mem::take(&mut batch.waiters)
+        });
+        (opened, full)
+    }
+
+    /// Takes the batch `id` if it was not taken yet.
+    fn take(&self, id: u64) -> Option<Vec<Waiter>> {
+        let mut batch = self.batch.lock().unwrap();
+        (batch.id == id && !batch.waiters.is_empty()).then(|| {
+            batch.id += 1;
+            mem::take(&mut batch.waiters)
+        })
+    }
+}
+
+
 /// `RedisGenerator` generates keys by incrementing a Redis counter.
 #[derive(Clone, Debug)]
 pub struct RedisGenerator {
-    /// A thread-safe pool of Redis clients.
-    pub(crate) pool: Arc<Client>,
+    /// The store holding the counter.
+    store: Arc<dyn CounterStore>,
+    /// The guard rejecting counter values that go backwards, if enabled.
+    guard: Option<Arc<CounterGuard>>,
+    /// The retries of increments failing with a connection error.
+    retry: RetryConfig,
+    /// The coalescing of concurrent increments, if enabled.
+    coalescer: Option<Arc<Coalescer>>,
 }
 
 
 impl RedisGenerator {
-    /// Creates a new `RedisGenerator`.
+    /// Creates a new `RedisGenerator`. Connections to Redis are opened on first use.
+    ///
+    /// With a high-water file, the guard starts from the persisted value and a task
+    /// persisting it every second is spawned, so this must then be called from within
+    /// a Tokio runtime.
     ///
     /// # Arguments
     ///
     /// * `config` - The Redis configuration.
-    pub fn new(config: &RedisConfig) -> Self {
-        let client = Client::open(config.url.clone()).unwrap();
-        Self {
-            pool: Arc::new(client),
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the generator, or a `GeneratorError::InvalidConfig` if the URL is invalid.
+    pub fn new(config: &RedisConfig) -> Result<Self, GeneratorError> {
+        let store = RedisStore::new(config)?;
+        let guard = config.monotonic_guard.then(|| {
+            let high_water = match &config.high_water_file {
+                Some(path) => load_high_water(path).unwrap_or_else(|err| {
+                    warn!("Cannot read the counter high-water mark from {path}, starting from 0: {err}");
+                    0
+                }),
+                None => 0,
+            };
+            CounterGuard::new(high_water, config.loss_threshold)
+        });
+        let mut generator = Self::with_store(Arc::new(store), guard).with_retry(config.retry);
+        if let Some(coalesce) = config.coalesce {
+            generator = generator.with_coalescing(coalesce);
+        }
+        if let (Some(guard), Some(path)) = (&generator.guard, &config.high_water_file) {
+            spawn_high_water_persistence(guard.clone(), path.clone());
+        }
+        Ok(generator)
+    }
+
+    /// Creates a new `RedisGenerator` on top of any counter store.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The store holding the counter.
+    /// * `guard` - The guard rejecting counter values that go backwards, if enabled.
+    pub fn with_store(store: Arc<dyn CounterStore>, guard: Option<CounterGuard>) -> Self {
+        Self { store, guard: guard.map(Arc::new), retry: RetryConfig::default(), coalescer: None }
+    }
+
+    /// Retries increments failing with a connection error according to `retry`.
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry policy.
+    pub fn with_retry(mut self, retry: RetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Merges concurrent increments into one `INCRBY` according to `coalesce`.
+    ///
+    /// # Arguments
+    ///
+    /// * `coalesce` - The batch window and maximum batch.
+    pub fn with_coalescing(mut self, coalesce: CoalesceConfig) -> Self {
+        self.coalescer = Some(Arc::new(Coalescer::new(coalesce)));
+        self
+    }
+
+    /// Reads the current value of the counter without incrementing it.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the counter value (0 if it was never set) or a `GeneratorError`.
+    pub async fn current_count(&self) -> Result<u128, GeneratorError> {
+        let res = self.store.get().await?;
+        res.map_or(Ok(0), |value| parse_counter(&value))
+    }
+
+    /// Raises the counter to `value` if it is currently lower. The counter is never decreased.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The minimum value the counter must have after the call.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the counter value after the call or a `GeneratorError`.
+    pub async fn raise_count_to(&self, value: u128) -> Result<u128, GeneratorError> {
+        let res = self.store.raise_to(value).await?;
+        parse_counter(&res)
+    }
+
+    /// Joins the open batch of increments and waits for its key. The caller opening a
+    /// batch starts the timer flushing it, and the caller filling it flushes it at once.
+    ///
+    /// Batches are flushed on spawned tasks, so a caller giving up never strands the others.
+    async fn coalesced_key(&self, coalescer: &Arc<Coalescer>) -> Result<u128, GeneratorError> {
+        let (sender, receiver) = oneshot::channel();
+        let (opened, full) = coalescer.join(sender);
+        if let Some(id) = opened {
+            let (generator, coalescer) = (self.clone(), coalescer.clone());
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_micros(coalescer.config.window_us)).await;
+                if let Some(waiters) = coalescer.take(id) {
+                    generator.flush(waiters).await;
+                }
+            });
+        }
+        if let Some(waiters) = full {
+            let generator = self.clone();
+            tokio::spawn(async move { generator.flush(waiters).await });
         }
+        receiver
+            .await
+            .map_err(|_| GeneratorError::UnknownError("Coalesced increment was dropped".to_string()))?
+    }
+
+    /// Reserves one key per waiter with a single `INCRBY` and hands them out, or hands
+    /// the error out to every waiter.
+    async fn flush(&self, waiters: Vec<Waiter>) {
+        match GeneratorInteger::generate_keys(self, waiters.len()).await {
+            Ok(keys) => {
+                for (waiter, key) in waiters.into_iter().zip(keys) {
+                    let _ = waiter.send(Ok(key));
+                }
+            }
+            Err(err) => {
+                for waiter in waiters {
+                    let _ = waiter.send(Err(err.clone()));
+                }
+            }
+        }
+    }
+}
+
+
+/// Spawns the task persisting the high-water mark of `guard` in `path` every second,
+/// whenever it changed.
+fn spawn_high_water_persistence(guard: Arc<CounterGuard>, path: String) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        let mut persisted = guard.high_water();
+        loop {
+            ticker.tick().await;
+            let high_water = guard.high_water();
+            if high_water == persisted {
+                continue;
+            }
+            match store_high_water(&path, high_water) {
+                Ok(()) => persisted = high_water,
+                Err(err) => warn!("Cannot persist the counter high-water mark to {path}: {err}"),
+            }
+        }
+    });
+}
+
+
+/// Parses a counter value returned by Redis. Counters are read as strings so that
+/// they are not limited to the width of `usize`.
+fn parse_counter(value: &str) -> Result<u128, GeneratorError> {
+    value
+        .parse::<u128>()
+        .map_err(|_| GeneratorError::UnknownError(format!("Invalid counter value: {value}")))
+}
+
+
+/// Runs `command` until it succeeds, fails with anything but a `GeneratorError::ConnectionError`,
+/// or `retry.max_attempts` attempts were made. Retries back off exponentially from
+/// `retry.base_delay_ms`.
+///
+/// An increment whose connection dropped after Redis applied it is retried as well, so
+/// a retry may skip a counter value but never repeats one.
+async fn with_retries<T, F, Fut>(retry: RetryConfig, mut command: F) -> Result<T, GeneratorError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, GeneratorError>>,
+{
+    let mut attempt = 1;
+    loop {
+        match command().await {
+            Err(GeneratorError::ConnectionError) if attempt < retry.max_attempts => {
+                let delay = retry.base_delay_ms.saturating_mul(1 << (attempt - 1).min(16));
+                warn!("Redis connection error on attempt {attempt}, retrying in {delay} ms");
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+
+/// Maps a `RedisError` to a `GeneratorError`, distinguishing connectivity problems and
+/// writes rejected by a read-only replica from any other failure.
+pub(crate) fn map_redis_error(err: RedisError) -> GeneratorError {
+    if err.is_timeout() || err.is_connection_refusal() || err.is_connection_dropped() {
+        GeneratorError::ConnectionError
+    } else if err.kind() == ErrorKind::ReadOnly {
+        GeneratorError::ReadOnly
+    } else {
+        GeneratorError::UnknownError(err.to_string())
+    }
+}
+
+
+/// Creates a pool of connections to a Redis server. No connection is opened until
+/// one is borrowed.
+///
+/// # Arguments
+///
+/// * `config` - The Redis configuration.
+///
+/// # Returns
+///
+/// A `Result` with the pool, or a `GeneratorError::InvalidConfig` if the URL is invalid.
+pub(crate) fn redis_pool(config: &RedisConfig) -> Result<Pool, GeneratorError> {
+    let mut pool_config = deadpool_redis::Config::from_url(config.url.clone());
+    pool_config.pool = Some(PoolConfig::new(config.pool_size));
+    pool_config
+        .create_pool(Some(Runtime::Tokio1))
+        .map_err(|err| GeneratorError::InvalidConfig(format!("Invalid Redis URL: {err}")))
+}
+
+
+/// Borrows a connection from `pool` until it is dropped.
+pub(crate) async fn pooled_connection(pool: &Pool) -> Result<Connection, GeneratorError> {
+    pool.get().await.map_err(map_pool_error)
+}
+
+
+/// Maps a failure to borrow a pooled connection to a `GeneratorError`.
+fn map_pool_error(err: PoolError) -> GeneratorError {
+    match err {
+        PoolError::Backend(err) => map_redis_error(err),
+        PoolError::Timeout(_) => GeneratorError::ConnectionError,
+        err => GeneratorError::UnknownError(err.to_string()),
     }
 }
 
@@ -34,21 +524,383 @@ impl RedisGenerator {
 #[async_trait]
 impl GeneratorInteger for RedisGenerator {
     /// Asynchronously generates a key by incrementing the "incr:count" counter in Redis.
+    /// The command is awaited on a pooled connection and never blocks the worker thread.
     ///
     /// # Returns
     ///
     /// A `Result` which is either the new integer key or a `GeneratorError`.
-    async fn generate_key(&self) -> Result<usize, GeneratorError> {
-        let con = self.pool.clone();
-        let mut cn: Client = (*con).clone();
-        let res = redis::cmd("INCR").arg("incr:count").query(&mut cn).map_err(|err| {
-            // TODO: Implement retries policies
-            if err.is_timeout() || err.is_connection_refusal() || err.is_connection_dropped() {
-                GeneratorError::ConnectionError
-            } else {
-                GeneratorError::UnknownError(err.to_string())
-            }
-        })?;
-        Ok(res)
+    async fn generate_key(&self) -> Result<u128, GeneratorError> {
+        if let Some(coalescer) = &self.coalescer {
+            return self.coalesced_key(coalescer).await;
+        }
+        let floor = self.guard.as_ref().map(|guard| guard.high_water());
+        let res = with_retries(self.retry, || self.store.incr()).await?;
+        let value = parse_counter(&res)?;
+        match (&self.guard, floor) {
+            (Some(guard), Some(floor)) => guard.check(value, floor),
+            _ => Ok(value),
+        }
+    }
+
+    /// Reserves `count` consecutive keys with a single `INCRBY` on the "incr:count" counter.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the keys in increasing order, or a `GeneratorError`.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<u128>, GeneratorError> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let floor = self.guard.as_ref().map(|guard| guard.high_water());
+        let res = with_retries(self.retry, || self.store.incr_by(count as u64)).await?;
+        let last = parse_counter(&res)?;
+        let first = last
+            .checked_sub(count as u128 - 1)
+            .ok_or_else(|| GeneratorError::UnknownError(format!("Invalid counter value: {last}")))?;
+        if let (Some(guard), Some(floor)) = (&self.guard, floor) {
+            guard.check(first, floor)?;
+            guard.check(last, floor)?;
+        }
+        Ok((first..=last).collect())
+    }
+
+    /// Sends a `PING` to the Redis server.
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.store.ping().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::eq;
+    use tokio::sync::Notify;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use crate::generator::{convert_to_string, Generator};
+
+    /// A store whose `INCR` only completes once the test opens the gate.
+    #[derive(Debug, Default)]
+    struct GatedStore {
+        gate: Notify,
+    }
+
+    #[async_trait]
+    impl CounterStore for GatedStore {
+        async fn incr(&self) -> Result<String, GeneratorError> {
+            self.gate.notified().await;
+            Ok("42".to_string())
+        }
+
+        async fn incr_by(&self, count: u64) -> Result<String, GeneratorError> {
+            Ok(count.to_string())
+        }
+
+        async fn get(&self) -> Result<Option<String>, GeneratorError> {
+            Ok(None)
+        }
+
+        async fn raise_to(&self, value: u128) -> Result<String, GeneratorError> {
+            Ok(value.to_string())
+        }
+
+        async fn ping(&self) -> Result<(), GeneratorError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_incr_does_not_block_the_runtime() {
+        let store = Arc::new(GatedStore::default());
+        let generator = RedisGenerator::with_store(store.clone(), Some(CounterGuard::default()));
+        let pending = tokio::spawn(async move { GeneratorInteger::generate_key(&generator).await });
+
+        // On a single-threaded runtime this only runs if the pending INCR yielded.
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+        store.gate.notify_one();
+        assert_eq!(pending.await.unwrap(), Ok(42));
+    }
+
+    #[tokio::test]
+    async fn test_store_values_are_parsed() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr().returning(|| Ok("not a number".to_string()));
+        store.expect_get().returning(|| Ok(None));
+        let generator = RedisGenerator::with_store(Arc::new(store), None);
+        assert!(matches!(GeneratorInteger::generate_key(&generator).await, Err(GeneratorError::UnknownError(_))));
+        assert_eq!(generator.current_count().await, Ok(0));
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_reserved_with_one_incrby() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr_by().with(eq(5)).times(1).returning(|_| Ok("105".to_string()));
+        let generator = RedisGenerator::with_store(Arc::new(store), Some(CounterGuard::default()));
+        let keys = Generator::generate_keys(&generator, 5).await.unwrap();
+        let expected: Vec<String> = (101..=105).map(|number| convert_to_string(number).unwrap()).collect();
+        assert_eq!(keys, expected);
+    }
+
+    #[tokio::test]
+    async fn test_batch_overlapping_seen_values_is_stale() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr_by().returning(|_| Ok("105".to_string()));
+        let generator = RedisGenerator::with_store(Arc::new(store), Some(CounterGuard::new(102, None)));
+        let err = GeneratorInteger::generate_keys(&generator, 5).await.unwrap_err();
+        assert_eq!(err, GeneratorError::StaleCounter { observed: 101, last_seen: 102 });
+    }
+
+    #[tokio::test]
+    async fn test_connection_errors_are_retried() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let mut store = MockCounterStore::new();
+        let counter = attempts.clone();
+        store.expect_incr().returning(move || match counter.fetch_add(1, Ordering::SeqCst) {
+            0 | 1 => Err(GeneratorError::ConnectionError),
+            _ => Ok("7".to_string()),
+        });
+        let generator = RedisGenerator::with_store(Arc::new(store), None)
+            .with_retry(RetryConfig { max_attempts: 3, base_delay_ms: 1 });
+        assert_eq!(GeneratorInteger::generate_key(&generator).await, Ok(7));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_exhausted() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr().times(2).returning(|| Err(GeneratorError::ConnectionError));
+        let generator = RedisGenerator::with_store(Arc::new(store), None)
+            .with_retry(RetryConfig { max_attempts: 2, base_delay_ms: 1 });
+        assert_eq!(GeneratorInteger::generate_key(&generator).await, Err(GeneratorError::ConnectionError));
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_are_not_retried() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr().times(1).returning(|| Err(GeneratorError::ReadOnly));
+        let generator = RedisGenerator::with_store(Arc::new(store), None)
+            .with_retry(RetryConfig { max_attempts: 3, base_delay_ms: 1 });
+        assert_eq!(GeneratorInteger::generate_key(&generator).await, Err(GeneratorError::ReadOnly));
+    }
+
+    fn coalescing(store: MockCounterStore, window_us: u64, max_batch: usize) -> Arc<RedisGenerator> {
+        let generator = RedisGenerator::with_store(Arc::new(store), None)
+            .with_coalescing(CoalesceConfig { window_us, max_batch });
+        Arc::new(generator)
+    }
+
+    async fn concurrent_keys(generator: &Arc<RedisGenerator>, count: usize) -> Vec<Result<u128, GeneratorError>> {
+        let tasks: Vec<_> = (0..count)
+            .map(|_| {
+                let generator = generator.clone();
+                tokio::spawn(async move { GeneratorInteger::generate_key(&*generator).await })
+            })
+            .collect();
+        let mut keys = Vec::with_capacity(count);
+        for task in tasks {
+            keys.push(task.await.unwrap());
+        }
+        keys
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_increments_are_coalesced() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let increments = Arc::new(AtomicUsize::new(0));
+        let mut store = MockCounterStore::new();
+        store.expect_incr().never();
+        let (value, calls) = (counter.clone(), increments.clone());
+        store.expect_incr_by().returning(move |count| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok((value.fetch_add(count, Ordering::SeqCst) + count).to_string())
+        });
+        let generator = coalescing(store, 10_000, 1000);
+        let keys: HashSet<u128> = concurrent_keys(&generator, 500).await.into_iter().map(Result::unwrap).collect();
+        assert_eq!(keys, (1..=500).collect());
+        assert!(increments.load(Ordering::SeqCst) < 10);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_full_batches_are_flushed_at_once() {
+        let counter = Arc::new(AtomicU64::new(0));
+        let mut store = MockCounterStore::new();
+        store.expect_incr_by().with(eq(10)).times(10).returning(move |count| {
+            Ok((counter.fetch_add(count, Ordering::SeqCst) + count).to_string())
+        });
+        // The window never closes within the test, so only full batches are flushed.
+        let generator = coalescing(store, 60_000_000, 10);
+        let keys: HashSet<u128> = concurrent_keys(&generator, 100).await.into_iter().map(Result::unwrap).collect();
+        assert_eq!(keys.len(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_coalesced_errors_reach_every_caller() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr_by().times(1).returning(|_| Err(GeneratorError::ConnectionError));
+        let generator = coalescing(store, 60_000_000, 3);
+        let keys = concurrent_keys(&generator, 3).await;
+        assert_eq!(keys, vec![Err(GeneratorError::ConnectionError); 3]);
+    }
+
+    #[test]
+    fn test_invalid_url_is_an_error() {
+        let config = RedisConfig {
+            url: "not-a-url".to_string(),
+            monotonic_guard: true,
+            pool_size: 1,
+            loss_threshold: None,
+            high_water_file: None,
+            retry: RetryConfig::default(),
+            coalesce: None,
+        };
+        assert!(matches!(RedisGenerator::new(&config), Err(GeneratorError::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn test_pool_errors_are_mapped() {
+        assert_eq!(map_pool_error(PoolError::Timeout(deadpool_redis::TimeoutType::Wait)), GeneratorError::ConnectionError);
+        let err = RedisError::from((ErrorKind::ReadOnly, "You can't write against a read only replica."));
+        assert_eq!(map_pool_error(PoolError::Backend(err)), GeneratorError::ReadOnly);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore = "requires a Redis at REDIS_URL"]
+    async fn test_concurrent_keys_share_the_pool() {
+        let config = RedisConfig {
+            url: std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://localhost:6379".to_string()),
+            monotonic_guard: true,
+            pool_size: 4,
+            loss_threshold: None,
+            high_water_file: None,
+            retry: RetryConfig::default(),
+            coalesce: None,
+        };
+        let generator = RedisGenerator::new(&config).unwrap();
+        let tasks: Vec<_> = (0..256)
+            .map(|_| {
+                let generator = generator.clone();
+                tokio::spawn(async move { GeneratorInteger::generate_key(&generator).await })
+            })
+            .collect();
+        let mut keys = Vec::new();
+        for task in tasks {
+            keys.push(task.await.unwrap().unwrap());
+        }
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 256);
+    }
+
+    #[test]
+    fn test_read_only_error_is_mapped() {
+        let err = RedisError::from((ErrorKind::ReadOnly, "You can't write against a read only replica."));
+        assert_eq!(map_redis_error(err), GeneratorError::ReadOnly);
+        let err = RedisError::from((ErrorKind::TypeError, "Response was of incompatible type"));
+        assert!(matches!(map_redis_error(err), GeneratorError::UnknownError(_)));
+    }
+
+    /// Checks `value` as if its `INCR` was sent after every earlier check completed.
+    fn check_in_order(guard: &CounterGuard, value: u128) -> Result<u128, GeneratorError> {
+        guard.check(value, guard.high_water())
+    }
+
+    #[test]
+    fn test_guard_accepts_increasing_values() {
+        let guard = CounterGuard::default();
+        assert_eq!(check_in_order(&guard, 1), Ok(1));
+        assert_eq!(check_in_order(&guard, 2), Ok(2));
+        assert_eq!(check_in_order(&guard, 10), Ok(10));
+    }
+
+    #[test]
+    fn test_guard_rejects_decreasing_values() {
+        let guard = CounterGuard::default();
+        assert_eq!(check_in_order(&guard, 100), Ok(100));
+        assert_eq!(check_in_order(&guard, 101), Ok(101));
+        assert_eq!(check_in_order(&guard, 42), Err(GeneratorError::StaleCounter { observed: 42, last_seen: 101 }));
+        assert_eq!(check_in_order(&guard, 101), Err(GeneratorError::StaleCounter { observed: 101, last_seen: 101 }));
+        assert_eq!(check_in_order(&guard, 102), Ok(102));
+    }
+
+    #[test]
+    fn test_guard_accepts_values_completing_out_of_order() {
+        let guard = CounterGuard::default();
+        let (floor_a, floor_b) = (guard.high_water(), guard.high_water());
+        assert_eq!(guard.check(6, floor_b), Ok(6));
+        assert_eq!(guard.check(5, floor_a), Ok(5));
+        assert_eq!(guard.high_water(), 6);
+        assert_eq!(check_in_order(&guard, 5), Err(GeneratorError::StaleCounter { observed: 5, last_seen: 6 }));
+    }
+
+    #[test]
+    fn test_guard_detects_lost_counter() {
+        let guard = CounterGuard::new(1_000_000, Some(1000));
+        // A lagging replica stays within the threshold.
+        assert_eq!(check_in_order(&guard, 999_500), Err(GeneratorError::StaleCounter { observed: 999_500, last_seen: 1_000_000 }));
+        // An evicted counter restarts from 1.
+        assert_eq!(check_in_order(&guard, 1), Err(GeneratorError::CounterLost { observed: 1, last_seen: 1_000_000 }));
+        assert_eq!(check_in_order(&guard, 1_000_001), Ok(1_000_001));
+    }
+
+    /// A store whose `INCR` replies arrive in the reverse order of the increments.
+    #[derive(Debug, Default)]
+    struct ReorderingStore {
+        counter: AtomicU64,
+    }
+
+    #[async_trait]
+    impl CounterStore for ReorderingStore {
+        async fn incr(&self) -> Result<String, GeneratorError> {
+            let value = self.counter.fetch_add(1, Ordering::SeqCst) + 1;
+            tokio::time::sleep(Duration::from_millis(100u64.saturating_sub(value * 5))).await;
+            Ok(value.to_string())
+        }
+
+        async fn incr_by(&self, count: u64) -> Result<String, GeneratorError> {
+            Ok((self.counter.fetch_add(count, Ordering::SeqCst) + count).to_string())
+        }
+
+        async fn get(&self) -> Result<Option<String>, GeneratorError> {
+            Ok(Some(self.counter.load(Ordering::SeqCst).to_string()))
+        }
+
+        async fn raise_to(&self, value: u128) -> Result<String, GeneratorError> {
+            Ok(value.to_string())
+        }
+
+        async fn ping(&self) -> Result<(), GeneratorError> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_guard_accepts_concurrent_increments() {
+        let generator = Arc::new(RedisGenerator::with_store(Arc::new(ReorderingStore::default()), Some(CounterGuard::default())));
+        let keys = concurrent_keys(&generator, 16).await;
+        let keys: HashSet<u128> = keys.into_iter().map(Result::unwrap).collect();
+        assert_eq!(keys, (1..=16).collect());
+        assert_eq!(generator.guard.as_ref().unwrap().high_water(), 16);
+    }
+
+    #[tokio::test]
+    async fn test_evicted_counter_fails_generation() {
+        let mut store = MockCounterStore::new();
+        store.expect_incr().returning(|| Ok("1".to_string()));
+        let generator = RedisGenerator::with_store(Arc::new(store), Some(CounterGuard::new(5000, Some(100))));
+        let err = GeneratorInteger::generate_key(&generator).await.unwrap_err();
+        assert_eq!(err, GeneratorError::CounterLost { observed: 1, last_seen: 5000 });
+        assert_eq!(tonic::Status::from(err).code(), tonic::Code::DataLoss);
+    }
+
+    #[test]
+    fn test_high_water_round_trip() {
+        let path = std::env::temp_dir().join(format!("keygen-high-water-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(load_high_water(path).unwrap(), 0);
+        store_high_water(path, 123_456).unwrap();
+        assert_eq!(load_high_water(path).unwrap(), 123_456);
+        std::fs::remove_file(path).unwrap();
     }
 }
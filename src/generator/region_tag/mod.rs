@@ -0,0 +1,148 @@
+//! This module defines a generator wrapper that embeds a region tag in the
+//! leading character of every key.
+//!
+//! The tag replaces the leading digit of the wrapped generator's key, which is reserved
+//! at startup (see `KeyLength::reserve_leading_digit`) so integer generators encode their
+//! numbers in the remaining digits and each region owns `62^(digits - 1)` keys. A key
+//! whose leading digit is not zero would lose part of its value to the tag and collide
+//! with another key, so it fails with `GeneratorError::KeySpaceExhausted` instead.
+//!
+//! The same wrapper writes the generator family prefix of `SCHEME_PREFIX`.
+use std::sync::Arc;
+use tonic::async_trait;
+use crate::generator::{max_number_for, Generator};
+use crate::generator::encoding::key_encoding;
+use crate::generator::error::GeneratorError;
+
+
+/// A generator that prefixes the keys of another generator with a region tag.
+#[derive(Debug)]
+pub struct RegionTagGenerator {
+    inner: Arc<dyn Generator>,
+    tag: char,
+}
+
+
+impl RegionTagGenerator {
+    /// Creates a new `RegionTagGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the untagged keys.
+    /// * `tag` - The region tag, already validated to be an alphabet character.
+    pub fn new(inner: Arc<dyn Generator>, tag: char) -> Self {
+        Self { inner, tag }
+    }
+
+    /// Replaces the leading zero digit of `key` with the tag.
+    fn tag_key(&self, key: String) -> Result<String, GeneratorError> {
+        let mut chars = key.chars();
+        if chars.next() != key_encoding().symbols().chars().next() {
+            return Err(overwritten_digit(key));
+        }
+        Ok(std::iter::once(self.tag).chain(chars).collect())
+    }
+}
+
+
+#[async_trait]
+impl Generator for RegionTagGenerator {
+    /// Generates a key from the wrapped generator and replaces its leading zero digit with the tag.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        self.tag_key(self.inner.generate_key().await?)
+    }
+
+    /// Generates a batch from the wrapped generator and tags every key of it.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        self.inner.generate_keys(count).await?.into_iter().map(|key| self.tag_key(key)).collect()
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        self.inner.collision_probability(issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.inner.health().await
+    }
+}
+
+
+/// Returns the error of a key whose leading digit is not zero and would be lost to the tag.
+fn overwritten_digit(key: String) -> GeneratorError {
+    let encoding = key_encoding();
+    let digits = key.chars().count().saturating_sub(encoding.key_length(1));
+    match encoding.decode(&key) {
+        Ok(number) => GeneratorError::KeySpaceExhausted { number, max: max_number_for(digits) },
+        Err(_) => GeneratorError::InvalidKey { key, check: "leading digit" },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::MockGenerator;
+
+    fn tagged(keys: Vec<&'static str>, tag: char) -> RegionTagGenerator {
+        let mut mock_gen = MockGenerator::new();
+        let mut keys = keys.into_iter();
+        mock_gen.expect_generate_key().returning(move || Ok(keys.next().unwrap().to_string()));
+        RegionTagGenerator::new(Arc::new(mock_gen), tag)
+    }
+
+    #[tokio::test]
+    async fn test_regions_are_distinguishable() {
+        let eu = tagged(vec!["00000001"], 'E');
+        let us = tagged(vec!["00000001"], 'U');
+        let eu_key = eu.generate_key().await.unwrap();
+        let us_key = us.generate_key().await.unwrap();
+        assert_eq!(eu_key, "E0000001");
+        assert_eq!(us_key, "U0000001");
+        assert_ne!(eu_key, us_key);
+    }
+
+    #[tokio::test]
+    async fn test_keys_unique_within_region() {
+        let generator = tagged(vec!["00000001", "00000002", "0000000z"], 'E');
+        let mut keys = Vec::new();
+        for _ in 0..3 {
+            keys.push(generator.generate_key().await.unwrap());
+        }
+        assert_eq!(keys, vec!["E0000001", "E0000002", "E000000z"]);
+    }
+
+    #[tokio::test]
+    async fn test_batch_keys_are_tagged() {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_keys().times(1)
+            .returning(|count| Ok((1..=count).map(|number| format!("0000000{number}")).collect()));
+        let generator = RegionTagGenerator::new(Arc::new(mock_gen), 'E');
+        assert_eq!(generator.generate_keys(3).await.unwrap(), vec!["E0000001", "E0000002", "E0000003"]);
+    }
+
+    #[tokio::test]
+    async fn test_nonzero_leading_digit_is_not_overwritten() {
+        let generator = tagged(vec!["10000001", "z000000-"], 'E');
+        assert_eq!(
+            generator.generate_key().await,
+            Err(GeneratorError::KeySpaceExhausted { number: 62_u128.pow(7) + 1, max: 62_u128.pow(7) - 1 })
+        );
+        assert_eq!(
+            generator.generate_key().await,
+            Err(GeneratorError::InvalidKey { key: "z000000-".to_string(), check: "leading digit" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scheme_prefixed_key_decodes() {
+        use crate::generator::encoding::Encoding;
+
+        let key = tagged(vec!["0000pnfq"], 'C').generate_key().await.unwrap();
+        assert_eq!(key, "C000pnfq");
+        assert_eq!(Encoding::Base62.decode(&key[1..]).unwrap(), 12345678);
+    }
+}
@@ -0,0 +1,88 @@
+//! This module defines a test generator returning keys from a configured list in order,
+//! so downstream integration tests get predictable keys without a Redis instance.
+//!
+//! Keys are not checked for uniqueness or format, and a wrapping sequence reissues
+//! them: never use this generator in production.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tonic::async_trait;
+use crate::config::{SequenceEnd, SequenceFixedConfig};
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+
+
+/// A generator returning a fixed sequence of keys.
+#[derive(Debug)]
+pub struct SequenceFixedGenerator {
+    keys: Vec<String>,
+    end: SequenceEnd,
+    position: AtomicUsize,
+}
+
+
+impl SequenceFixedGenerator {
+    /// Creates a new `SequenceFixedGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The fixed sequence configuration, with at least one key.
+    pub fn new(config: &SequenceFixedConfig) -> Self {
+        Self {
+            keys: config.keys.clone(),
+            end: config.end,
+            position: AtomicUsize::new(0),
+        }
+    }
+}
+
+
+#[async_trait]
+impl Generator for SequenceFixedGenerator {
+    /// Returns the next key of the sequence, wrapping around or failing at the end.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        let position = self.position.fetch_add(1, Ordering::SeqCst);
+        let index = match self.end {
+            SequenceEnd::Wrap => position % self.keys.len(),
+            SequenceEnd::Error => position,
+        };
+        self.keys.get(index).cloned().ok_or(GeneratorError::SequenceExhausted)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sequence(end: SequenceEnd) -> SequenceFixedGenerator {
+        SequenceFixedGenerator::new(&SequenceFixedConfig {
+            keys: vec!["key00001".to_string(), "key00002".to_string()],
+            end,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_returns_keys_in_order() {
+        let generator = sequence(SequenceEnd::Error);
+        assert_eq!(generator.generate_key().await.unwrap(), "key00001");
+        assert_eq!(generator.generate_key().await.unwrap(), "key00002");
+    }
+
+    #[tokio::test]
+    async fn test_wraps_at_end() {
+        let generator = sequence(SequenceEnd::Wrap);
+        let mut keys = Vec::new();
+        for _ in 0..5 {
+            keys.push(generator.generate_key().await.unwrap());
+        }
+        assert_eq!(keys, vec!["key00001", "key00002", "key00001", "key00002", "key00001"]);
+    }
+
+    #[tokio::test]
+    async fn test_errors_at_end() {
+        let generator = sequence(SequenceEnd::Error);
+        generator.generate_key().await.unwrap();
+        generator.generate_key().await.unwrap();
+        assert_eq!(generator.generate_key().await.unwrap_err(), GeneratorError::SequenceExhausted);
+        assert_eq!(generator.generate_key().await.unwrap_err(), GeneratorError::SequenceExhausted);
+    }
+}
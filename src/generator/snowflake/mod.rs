@@ -0,0 +1,164 @@
+//! This module defines a Snowflake-style key generator, composing a 64-bit ID from a
+//! timestamp, a worker id and a per-millisecond sequence, without any shared store.
+//!
+//! From the most significant bit, an ID holds a zero bit, 41 bits of milliseconds
+//! since `SNOWFLAKE_EPOCH_MS`, 10 bits of worker id and 12 bits of sequence. Every
+//! instance needs a distinct worker id, and IDs only fit in keys of 11 base 62 digits
+//! or more. Once 4096 IDs were issued within one millisecond, the generator spins until
+//! the next millisecond. If the clock moves backwards, generation fails until it catches
+//! up, since IDs could otherwise repeat.
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tonic::async_trait;
+use crate::config::SnowflakeConfig;
+use crate::generator::GeneratorInteger;
+use crate::generator::error::GeneratorError;
+
+/// The start of the timestamps, 2024-01-01T00:00:00Z in milliseconds since the Unix epoch.
+pub const SNOWFLAKE_EPOCH_MS: u64 = 1_704_067_200_000;
+/// The number of bits of the worker id.
+pub const WORKER_ID_BITS: u32 = 10;
+/// The number of bits of the per-millisecond sequence.
+const SEQUENCE_BITS: u32 = 12;
+/// The number of bits of the timestamp.
+const TIMESTAMP_BITS: u32 = 41;
+/// The highest sequence value within one millisecond.
+const MAX_SEQUENCE: u64 = (1 << SEQUENCE_BITS) - 1;
+/// The highest worker id.
+pub const MAX_WORKER_ID: u64 = (1 << WORKER_ID_BITS) - 1;
+
+
+/// The timestamp and sequence of the last issued ID.
+#[derive(Debug, Default)]
+struct SnowflakeState {
+    last_ms: u64,
+    sequence: u64,
+}
+
+
+/// A key generator composing Snowflake-style IDs.
+#[derive(Debug)]
+pub struct SnowflakeGenerator {
+    worker_id: u64,
+    state: Mutex<SnowflakeState>,
+    /// Returns the current time in milliseconds since the Unix epoch.
+    clock: fn() -> u64,
+}
+
+
+impl SnowflakeGenerator {
+    /// Creates a new `SnowflakeGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Snowflake generator configuration.
+    pub fn new(config: &SnowflakeConfig) -> Self {
+        Self::with_clock(config.worker_id, system_clock)
+    }
+
+    /// Creates a new `SnowflakeGenerator` reading the time from `clock`.
+    fn with_clock(worker_id: u64, clock: fn() -> u64) -> Self {
+        Self { worker_id, state: Mutex::new(SnowflakeState::default()), clock }
+    }
+
+    /// Composes the next ID.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the ID, or a `GeneratorError::UnknownError` if the clock moved
+    /// backwards or is outside the 41-bit timestamp range.
+    fn next_id(&self) -> Result<u64, GeneratorError> {
+        let mut state = self.state.lock().unwrap();
+        let mut now = (self.clock)();
+        if now < state.last_ms {
+            return Err(GeneratorError::UnknownError(format!("Clock moved backwards by {} ms", state.last_ms - now)));
+        }
+        if now == state.last_ms {
+            state.sequence = (state.sequence + 1) & MAX_SEQUENCE;
+            if state.sequence == 0 {
+                while now <= state.last_ms {
+                    std::hint::spin_loop();
+                    now = (self.clock)();
+                }
+            }
+        } else {
+            state.sequence = 0;
+        }
+        state.last_ms = now;
+
+        let timestamp = now
+            .checked_sub(SNOWFLAKE_EPOCH_MS)
+            .filter(|timestamp| *timestamp < 1 << TIMESTAMP_BITS)
+            .ok_or_else(|| GeneratorError::UnknownError(format!("Clock {now} is outside the Snowflake timestamp range")))?;
+        Ok((timestamp << (WORKER_ID_BITS + SEQUENCE_BITS)) | (self.worker_id << SEQUENCE_BITS) | state.sequence)
+    }
+}
+
+
+/// Returns the system time in milliseconds since the Unix epoch.
+fn system_clock() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as u64)
+}
+
+
+#[async_trait]
+impl GeneratorInteger for SnowflakeGenerator {
+    async fn generate_key(&self) -> Result<u128, GeneratorError> {
+        Ok(self.next_id()? as u128)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[tokio::test]
+    async fn test_ids_are_monotonic() {
+        let generator = SnowflakeGenerator::new(&SnowflakeConfig { worker_id: 7 });
+        let mut previous = generator.generate_key().await.unwrap();
+        for _ in 0..10_000 {
+            let id = generator.generate_key().await.unwrap();
+            assert!(id > previous);
+            previous = id;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_worker_id_is_embedded() {
+        let generator = SnowflakeGenerator::new(&SnowflakeConfig { worker_id: 0b1010110011 });
+        let id = generator.generate_key().await.unwrap();
+        assert_eq!((id >> SEQUENCE_BITS) & MAX_WORKER_ID as u128, 0b1010110011);
+        assert!(id < 1 << 63);
+    }
+
+    #[test]
+    fn test_sequence_rollover_waits_for_next_millisecond() {
+        static READS: AtomicU64 = AtomicU64::new(0);
+        // Every millisecond lasts 10000 clock reads, more than the 4096 IDs it can hold.
+        fn slow_clock() -> u64 {
+            SNOWFLAKE_EPOCH_MS + READS.fetch_add(1, Ordering::SeqCst) / 10_000
+        }
+        let generator = SnowflakeGenerator::with_clock(1, slow_clock);
+        let ids: Vec<u64> = (0..=MAX_SEQUENCE + 1).map(|_| generator.next_id().unwrap()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        let last = ids[ids.len() - 1];
+        assert_eq!(last & MAX_SEQUENCE, 0);
+        assert_eq!(last >> (WORKER_ID_BITS + SEQUENCE_BITS), 1);
+    }
+
+    #[test]
+    fn test_clock_going_backwards_fails() {
+        static NOW: AtomicU64 = AtomicU64::new(SNOWFLAKE_EPOCH_MS + 100);
+        fn clock() -> u64 {
+            NOW.load(Ordering::SeqCst)
+        }
+        let generator = SnowflakeGenerator::with_clock(1, clock);
+        assert!(generator.next_id().is_ok());
+        NOW.store(SNOWFLAKE_EPOCH_MS + 95, Ordering::SeqCst);
+        assert_eq!(generator.next_id(), Err(GeneratorError::UnknownError("Clock moved backwards by 5 ms".to_string())));
+        NOW.store(SNOWFLAKE_EPOCH_MS + 101, Ordering::SeqCst);
+        assert!(generator.next_id().is_ok());
+    }
+}
@@ -0,0 +1,236 @@
+//! This module defines a key generator backed by a primary Redis counter with a
+//! warm standby Redis that takes over when the primary becomes unreachable.
+//!
+//! The standby does not replicate the primary. Instead, a background task reads the
+//! primary counter every `mirror_interval_ms` and remembers the highest value seen.
+//! On failover the standby counter is raised to `last_seen + failover_margin` before
+//! serving keys. Keys issued by the primary after the last mirror read are unknown to
+//! the standby, so:
+//!
+//! * if the primary issued more than `failover_margin` keys since the last read,
+//!   the standby overlaps with them and reissues keys;
+//! * otherwise the keys between the primary's real position and the standby's start
+//!   are never issued (a gap).
+//!
+//! `failover_margin` should therefore be larger than the number of keys the primary
+//! can issue within one mirror interval. Once failed over, the generator keeps using
+//! the standby until the process restarts.
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tonic::async_trait;
+use tracing::{error, warn};
+use crate::config::{RedisConfig, StandbyConfig};
+use crate::generator::GeneratorInteger;
+use crate::generator::error::GeneratorError;
+use crate::generator::redis::RedisGenerator;
+
+
+/// Tracks the highest primary counter value observed by this process.
+#[derive(Debug, Default)]
+pub struct CounterMirror {
+    last_seen: Mutex<u128>,
+}
+
+
+impl CounterMirror {
+    /// Records an observed counter value. Values lower than the current one are ignored,
+    /// so stale reads never move the mirror backwards.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The observed counter value.
+    pub fn observe(&self, value: u128) {
+        let mut last_seen = self.last_seen.lock().unwrap();
+        *last_seen = (*last_seen).max(value);
+    }
+
+    /// Returns the highest counter value observed so far.
+    pub fn last_seen(&self) -> u128 {
+        *self.last_seen.lock().unwrap()
+    }
+}
+
+
+/// Computes the position the standby counter must start from on failover.
+///
+/// # Arguments
+///
+/// * `last_seen` - The highest primary counter value observed.
+/// * `margin` - The number of keys assumed to have been issued since the last observation.
+///
+/// # Returns
+///
+/// The counter value the standby should be raised to.
+pub fn failover_position(last_seen: u128, margin: u128) -> u128 {
+    last_seen.saturating_add(margin)
+}
+
+
+/// A key generator that uses a primary Redis and fails over to a standby Redis.
+#[derive(Clone, Debug)]
+pub struct StandbyRedisGenerator {
+    primary: RedisGenerator,
+    standby: RedisGenerator,
+    mirror: Arc<CounterMirror>,
+    failed_over: Arc<AtomicBool>,
+    failover_margin: u128,
+}
+
+
+impl StandbyRedisGenerator {
+    /// Creates a new `StandbyRedisGenerator` and starts the mirror task.
+    /// Must be called from within a Tokio runtime.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The primary Redis configuration.
+    /// * `standby_config` - The standby Redis and mirroring configuration.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the generator, or a `GeneratorError::InvalidConfig` if either URL is invalid.
+    pub fn new(config: &RedisConfig, standby_config: &StandbyConfig) -> Result<Self, GeneratorError> {
+        let generator = Self {
+            primary: RedisGenerator::new(config)?,
+            standby: RedisGenerator::new(&RedisConfig {
+                url: standby_config.url.clone(),
+                high_water_file: None,
+                ..config.clone()
+            })?,
+            mirror: Arc::new(CounterMirror::default()),
+            failed_over: Arc::new(AtomicBool::new(false)),
+            failover_margin: standby_config.failover_margin,
+        };
+        generator.spawn_mirror(Duration::from_millis(standby_config.mirror_interval_ms));
+        Ok(generator)
+    }
+
+    /// Spawns the task that periodically reads the primary counter into the mirror.
+    /// The task stops once the generator has failed over.
+    fn spawn_mirror(&self, interval: Duration) {
+        let primary = self.primary.clone();
+        let mirror = self.mirror.clone();
+        let failed_over = self.failed_over.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            while !failed_over.load(Ordering::SeqCst) {
+                ticker.tick().await;
+                match primary.current_count().await {
+                    Ok(value) => mirror.observe(value),
+                    Err(err) => warn!(error_code = err.code(), "Failed to mirror primary counter: {err}"),
+                }
+            }
+        });
+    }
+
+    /// Raises the standby counter to the failover position and switches to the standby.
+    async fn fail_over(&self) -> Result<(), GeneratorError> {
+        let position = failover_position(self.mirror.last_seen(), self.failover_margin);
+        self.standby.raise_count_to(position).await?;
+        if !self.failed_over.swap(true, Ordering::SeqCst) {
+            error!("Primary Redis unreachable, failed over to standby at counter {position}");
+        }
+        Ok(())
+    }
+}
+
+
+#[async_trait]
+impl GeneratorInteger for StandbyRedisGenerator {
+    /// Generates a key from the primary, or from the standby once the primary is unreachable.
+    /// If the standby also fails while failing over, both errors are reported.
+    async fn generate_key(&self) -> Result<u128, GeneratorError> {
+        if !self.failed_over.load(Ordering::SeqCst) {
+            match self.primary.generate_key().await {
+                Ok(value) => {
+                    self.mirror.observe(value);
+                    return Ok(value);
+                }
+                Err(err @ GeneratorError::ConnectionError) => {
+                    let standby = async {
+                        self.fail_over().await?;
+                        self.standby.generate_key().await
+                    };
+                    return standby.await.map_err(|standby_err| GeneratorError::AllGeneratorsFailed(vec![err, standby_err]));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        self.standby.generate_key().await
+    }
+
+    /// The generator is degraded once it has failed over to the standby.
+    fn is_degraded(&self) -> bool {
+        self.failed_over.load(Ordering::SeqCst)
+    }
+
+    /// The generator is healthy while the Redis it generates from, or the standby it can
+    /// fail over to, answers.
+    async fn health(&self) -> Result<(), GeneratorError> {
+        if !self.failed_over.load(Ordering::SeqCst) && self.primary.health().await.is_ok() {
+            return Ok(());
+        }
+        self.standby.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::redis::{CounterGuard, MockCounterStore};
+
+    fn standby_generator(primary: MockCounterStore, standby: MockCounterStore) -> StandbyRedisGenerator {
+        StandbyRedisGenerator {
+            primary: RedisGenerator::with_store(Arc::new(primary), Some(CounterGuard::default())),
+            standby: RedisGenerator::with_store(Arc::new(standby), Some(CounterGuard::default())),
+            mirror: Arc::new(CounterMirror::default()),
+            failed_over: Arc::new(AtomicBool::new(false)),
+            failover_margin: 1000,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failover_serves_from_standby() {
+        let mut primary = MockCounterStore::new();
+        primary.expect_incr().returning(|| Err(GeneratorError::ConnectionError));
+        let mut standby = MockCounterStore::new();
+        standby.expect_raise_to().returning(|value| Ok(value.to_string()));
+        standby.expect_incr().returning(|| Ok("1001".to_string()));
+        let generator = standby_generator(primary, standby);
+        assert_eq!(generator.generate_key().await, Ok(1001));
+        assert!(GeneratorInteger::is_degraded(&generator));
+    }
+
+    #[tokio::test]
+    async fn test_both_failures_are_reported() {
+        let mut primary = MockCounterStore::new();
+        primary.expect_incr().returning(|| Err(GeneratorError::ConnectionError));
+        let mut standby = MockCounterStore::new();
+        standby.expect_raise_to().returning(|_| Err(GeneratorError::ReadOnly));
+        let generator = standby_generator(primary, standby);
+        assert_eq!(
+            generator.generate_key().await,
+            Err(GeneratorError::AllGeneratorsFailed(vec![GeneratorError::ConnectionError, GeneratorError::ReadOnly]))
+        );
+    }
+
+    #[test]
+    fn test_mirror_tracks_highest_value() {
+        let mirror = CounterMirror::default();
+        assert_eq!(mirror.last_seen(), 0);
+        mirror.observe(10);
+        mirror.observe(25);
+        assert_eq!(mirror.last_seen(), 25);
+        mirror.observe(20);
+        assert_eq!(mirror.last_seen(), 25);
+    }
+
+    #[test]
+    fn test_failover_position() {
+        assert_eq!(failover_position(0, 1000), 1000);
+        assert_eq!(failover_position(12345, 1000), 13345);
+        assert_eq!(failover_position(u128::MAX - 1, 1000), u128::MAX);
+    }
+}
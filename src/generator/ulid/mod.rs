@@ -0,0 +1,169 @@
+//! This module defines a key generator returning ULIDs, which are both time-ordered and
+//! collision resistant without any shared store.
+//!
+//! A ULID is 48 bits of milliseconds since the Unix epoch followed by 80 random bits,
+//! written as 26 Crockford base 32 characters, regardless of `NUMBER_DIGITS` and
+//! `KEY_ENCODING`. Keys issued within the same millisecond reuse the previous random
+//! component plus one, so they still sort in issuance order. If the clock moves
+//! backwards, the last timestamp is kept until the clock catches up.
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::Rng;
+use tonic::async_trait;
+use crate::generator::Generator;
+use crate::generator::encoding::CROCKFORD_ALPHABET;
+use crate::generator::error::GeneratorError;
+
+/// The number of random bits of a ULID.
+const RANDOM_BITS: u32 = 80;
+/// The number of characters of an encoded ULID.
+pub const ULID_LENGTH: usize = 26;
+
+
+/// The timestamp and random component of the last issued ULID.
+#[derive(Debug, Default)]
+struct UlidState {
+    last_ms: u64,
+    random: u128,
+}
+
+
+/// A key generator returning ULIDs.
+#[derive(Debug)]
+pub struct UlidGenerator {
+    state: Mutex<UlidState>,
+    /// Returns the current time in milliseconds since the Unix epoch.
+    clock: fn() -> u64,
+}
+
+
+impl UlidGenerator {
+    /// Creates a new `UlidGenerator`.
+    pub fn new() -> Self {
+        Self::with_clock(system_clock)
+    }
+
+    /// Creates a new `UlidGenerator` reading the time from `clock`.
+    fn with_clock(clock: fn() -> u64) -> Self {
+        Self { state: Mutex::new(UlidState::default()), clock }
+    }
+
+    /// Composes the next ULID.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the ULID, or a `GeneratorError::UnknownError` if more ULIDs were
+    /// requested within one millisecond than its random component can order.
+    fn next_ulid(&self) -> Result<u128, GeneratorError> {
+        let mut state = self.state.lock().unwrap();
+        let now = (self.clock)().max(state.last_ms);
+        if now == state.last_ms {
+            state.random += 1;
+            if state.random >> RANDOM_BITS != 0 {
+                return Err(GeneratorError::UnknownError("ULID random component overflowed within one millisecond".to_string()));
+            }
+        } else {
+            state.last_ms = now;
+            state.random = rand::rng().random::<u128>() >> (128 - RANDOM_BITS);
+        }
+        Ok(((now as u128) << RANDOM_BITS) | state.random)
+    }
+}
+
+
+impl Default for UlidGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+/// Returns the system time in milliseconds since the Unix epoch.
+fn system_clock() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_millis() as u64)
+}
+
+
+/// Encodes a ULID as 26 Crockford base 32 characters.
+fn encode(ulid: u128) -> String {
+    (0..ULID_LENGTH)
+        .map(|i| CROCKFORD_ALPHABET.as_bytes()[((ulid >> (5 * (ULID_LENGTH - 1 - i))) & 31) as usize] as char)
+        .collect()
+}
+
+
+#[async_trait]
+impl Generator for UlidGenerator {
+    /// Generates a new ULID.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        Ok(encode(self.next_ulid()?))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(0), "00000000000000000000000000");
+        assert_eq!(encode(u128::MAX), "7ZZZZZZZZZZZZZZZZZZZZZZZZZ");
+        assert_eq!(encode(1_469_918_176_385_u128 << RANDOM_BITS), "01ARYZ6S410000000000000000");
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_crockford_strings_in_creation_order() {
+        let generator = UlidGenerator::new();
+        let first = generator.generate_key().await.unwrap();
+        let second = generator.generate_key().await.unwrap();
+        assert_eq!(first.len(), ULID_LENGTH);
+        assert!(first.chars().chain(second.chars()).all(|c| CROCKFORD_ALPHABET.contains(c)));
+        assert!(first < second);
+    }
+
+    #[tokio::test]
+    async fn test_keys_sort_across_timestamps() {
+        static NOW: AtomicU64 = AtomicU64::new(1_700_000_000_000);
+        fn clock() -> u64 {
+            NOW.fetch_add(1, Ordering::SeqCst)
+        }
+        let generator = UlidGenerator::with_clock(clock);
+        let keys: Vec<String> = collect_keys(&generator, 100).await;
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(keys.iter().all(|key| key.len() == ULID_LENGTH));
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_unique_within_a_millisecond() {
+        fn frozen_clock() -> u64 {
+            1_700_000_000_000
+        }
+        let generator = UlidGenerator::with_clock(frozen_clock);
+        let keys = collect_keys(&generator, 1000).await;
+        assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(keys.iter().all(|key| key.starts_with("01HF7YAT00")));
+    }
+
+    #[test]
+    fn test_clock_going_backwards_keeps_order() {
+        static NOW: AtomicU64 = AtomicU64::new(1_700_000_000_100);
+        fn clock() -> u64 {
+            NOW.load(Ordering::SeqCst)
+        }
+        let generator = UlidGenerator::with_clock(clock);
+        let first = generator.next_ulid().unwrap();
+        NOW.store(1_700_000_000_095, Ordering::SeqCst);
+        let second = generator.next_ulid().unwrap();
+        assert_eq!(second, first + 1);
+    }
+
+    async fn collect_keys(generator: &UlidGenerator, count: usize) -> Vec<String> {
+        let mut keys = Vec::with_capacity(count);
+        for _ in 0..count {
+            keys.push(generator.generate_key().await.unwrap());
+        }
+        keys
+    }
+}
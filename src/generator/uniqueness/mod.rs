@@ -0,0 +1,245 @@
+//! This module defines a generator wrapper that checks candidate keys against an
+//! external uniqueness service, such as a central Bloom filter of issued keys, and
+//! re-rolls probable hits.
+//!
+//! The service is asked with `GET <UNIQUENESS_SERVICE_URL>/<key>` and answers `200`
+//! if the key was probably issued already, or `404` if it was not. A Bloom filter has
+//! false positives but no false negatives, so a hit only costs a re-roll. Only
+//! generators that can draw a key twice (`random`) are checked. The key is
+//! percent-encoded as one path segment, since `ALPHABET` may hold `/`, `?` or `#`.
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use reqwest::{StatusCode, Url};
+use tonic::async_trait;
+use tracing::warn;
+use crate::config::UniquenessConfig;
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+use crate::instrumentation::{record_retry_exhausted, REASON_UNIQUENESS};
+
+#[cfg(test)]
+use mockall::automock;
+
+
+/// A trait for services tracking the keys issued so far.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait UniquenessService: Debug + Send + Sync {
+    /// Checks whether `key` was probably issued already.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is `true` on a probable hit, or a `GeneratorError` if the
+    /// service cannot answer.
+    async fn probably_issued(&self, key: &str) -> Result<bool, GeneratorError>;
+}
+
+
+/// A `UniquenessService` reached over HTTP.
+#[derive(Clone, Debug)]
+pub struct HttpUniquenessService {
+    client: reqwest::Client,
+    url: Url,
+}
+
+
+impl HttpUniquenessService {
+    /// Creates a new `HttpUniquenessService`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The uniqueness service configuration.
+    pub fn new(config: &UniquenessConfig) -> Result<Self, GeneratorError> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(|err| GeneratorError::InvalidConfig(format!("Invalid uniqueness service client: {err}")))?;
+        let url = Url::parse(&config.url)
+            .ok()
+            .filter(|url| !url.cannot_be_a_base())
+            .ok_or_else(|| GeneratorError::InvalidConfig(format!("Invalid uniqueness service URL: {}", config.url)))?;
+        Ok(Self { client, url })
+    }
+
+    /// Returns the URL asking about `key`, with the key percent-encoded as the last
+    /// path segment.
+    fn key_url(&self, key: &str) -> Url {
+        let mut url = self.url.clone();
+        if let Ok(mut segments) = url.path_segments_mut() {
+            segments.pop_if_empty().push(key);
+        }
+        url
+    }
+}
+
+
+#[async_trait]
+impl UniquenessService for HttpUniquenessService {
+    async fn probably_issued(&self, key: &str) -> Result<bool, GeneratorError> {
+        let response = self
+            .client
+            .get(self.key_url(key))
+            .send()
+            .await
+            .map_err(|_| GeneratorError::ConnectionError)?;
+        match response.status() {
+            StatusCode::OK => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_server_error() => Err(GeneratorError::ConnectionError),
+            status => Err(GeneratorError::UnknownError(format!("Unexpected uniqueness service status: {status}"))),
+        }
+    }
+}
+
+
+/// A generator that re-rolls the keys of another generator that the uniqueness
+/// service reports as probably issued.
+#[derive(Debug)]
+pub struct UniquenessCheckGenerator {
+    inner: Arc<dyn Generator>,
+    service: Arc<dyn UniquenessService>,
+    fail_open: bool,
+    max_rerolls: usize,
+}
+
+
+impl UniquenessCheckGenerator {
+    /// Creates a new `UniquenessCheckGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `service` - The service tracking the issued keys.
+    /// * `fail_open` - Whether keys are issued unchecked while the service is unavailable.
+    /// * `max_rerolls` - How many times a probable hit is re-rolled before giving up.
+    pub fn new(inner: Arc<dyn Generator>, service: Arc<dyn UniquenessService>, fail_open: bool, max_rerolls: usize) -> Self {
+        Self { inner, service, fail_open, max_rerolls }
+    }
+
+    /// Creates an HTTP-backed `UniquenessCheckGenerator` from its configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `config` - The uniqueness service configuration.
+    pub fn from_config(inner: Arc<dyn Generator>, config: &UniquenessConfig) -> Result<Self, GeneratorError> {
+        let service = HttpUniquenessService::new(config)?;
+        Ok(Self::new(inner, Arc::new(service), config.fail_open, config.max_rerolls))
+    }
+
+    /// Checks whether `key` may be issued, i.e. it is not a probable hit or the service is
+    /// unavailable and the generator fails open.
+    async fn is_issuable(&self, key: &str) -> Result<bool, GeneratorError> {
+        match self.service.probably_issued(key).await {
+            Ok(issued) => Ok(!issued),
+            Err(err @ GeneratorError::ConnectionError) if self.fail_open => {
+                warn!(error_code = err.code(), "Uniqueness service unavailable, issuing key {key} unchecked");
+                Ok(true)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+
+#[async_trait]
+impl Generator for UniquenessCheckGenerator {
+    /// Generates keys from the wrapped generator until one is not a probable hit.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        for _ in 0..=self.max_rerolls {
+            let key = self.inner.generate_key().await?;
+            if self.is_issuable(&key).await? {
+                return Ok(key);
+            }
+        }
+        record_retry_exhausted(REASON_UNIQUENESS);
+        Err(GeneratorError::RetriesExhausted("every generated key was probably issued".to_string()))
+    }
+
+    /// Generates a batch from the wrapped generator and re-rolls the probable hits of it one
+    /// by one.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        let mut keys = Vec::with_capacity(count);
+        for key in self.inner.generate_keys(count).await? {
+            if self.is_issuable(&key).await? {
+                keys.push(key);
+            } else {
+                keys.push(self.generate_key().await?);
+            }
+        }
+        Ok(keys)
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        self.inner.collision_probability(issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.inner.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockall::predicate::eq;
+    use tracing_test::traced_test;
+    use crate::generator::MockGenerator;
+
+    fn checked(keys: Vec<&'static str>, service: MockUniquenessService, fail_open: bool) -> UniquenessCheckGenerator {
+        let mut mock_gen = MockGenerator::new();
+        let mut keys = keys.into_iter();
+        mock_gen.expect_generate_key().returning(move || Ok(keys.next().unwrap().to_string()));
+        UniquenessCheckGenerator::new(Arc::new(mock_gen), Arc::new(service), fail_open, 2)
+    }
+
+    #[test]
+    fn test_key_is_percent_encoded() {
+        let config = UniquenessConfig { url: "http://bloom:8080/keys/".to_string(), fail_open: true, timeout_ms: 100, max_rerolls: 2 };
+        let service = HttpUniquenessService::new(&config).unwrap();
+        assert_eq!(service.key_url("aaaaaaa1").as_str(), "http://bloom:8080/keys/aaaaaaa1");
+        assert_eq!(service.key_url("a/b?c#d%").as_str(), "http://bloom:8080/keys/a%2Fb%3Fc%23d%25");
+    }
+
+    #[tokio::test]
+    async fn test_hit_is_rerolled() {
+        let mut service = MockUniquenessService::new();
+        service.expect_probably_issued().with(eq("aaaaaaa1")).returning(|_| Ok(true));
+        service.expect_probably_issued().with(eq("aaaaaaa2")).returning(|_| Ok(false));
+        let generator = checked(vec!["aaaaaaa1", "aaaaaaa2"], service, true);
+        assert_eq!(generator.generate_key().await.unwrap(), "aaaaaaa2");
+    }
+
+    #[tokio::test]
+    async fn test_hits_are_capped() {
+        let mut service = MockUniquenessService::new();
+        service.expect_probably_issued().times(3).returning(|_| Ok(true));
+        let generator = checked(vec!["aaaaaaa1", "aaaaaaa2", "aaaaaaa3"], service, true);
+        let err = generator.generate_key().await.unwrap_err();
+        assert_eq!(err, GeneratorError::RetriesExhausted("every generated key was probably issued".to_string()));
+    }
+
+    #[traced_test]
+    #[tokio::test]
+    async fn test_unavailable_service_fails_open() {
+        let mut service = MockUniquenessService::new();
+        service.expect_probably_issued().returning(|_| Err(GeneratorError::ConnectionError));
+        let generator = checked(vec!["aaaaaaa1"], service, true);
+        assert_eq!(generator.generate_key().await.unwrap(), "aaaaaaa1");
+        assert!(logs_contain("Uniqueness service unavailable, issuing key aaaaaaa1 unchecked"));
+    }
+
+    #[tokio::test]
+    async fn test_unavailable_service_fails_closed() {
+        let mut service = MockUniquenessService::new();
+        service.expect_probably_issued().returning(|_| Err(GeneratorError::ConnectionError));
+        let generator = checked(vec!["aaaaaaa1"], service, false);
+        assert_eq!(generator.generate_key().await, Err(GeneratorError::ConnectionError));
+    }
+}
@@ -0,0 +1,73 @@
+//! This module defines a key generator returning UUIDs, for deployments that need no
+//! Redis but stronger collision resistance than the random generator.
+//!
+//! Keys are hyphenated lowercase UUIDs, 36 characters long, regardless of
+//! `NUMBER_DIGITS` and `KEY_ENCODING`. Version 4 UUIDs carry 122 random bits;
+//! version 7 UUIDs start with a millisecond timestamp, so they sort in issuance order.
+use tonic::async_trait;
+use uuid::Uuid;
+use crate::config::{UuidConfig, UuidVersion};
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+
+
+/// A key generator returning UUIDs.
+#[derive(Clone, Debug)]
+pub struct UuidGenerator {
+    version: UuidVersion,
+}
+
+
+impl UuidGenerator {
+    /// Creates a new `UuidGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The UUID generator configuration.
+    pub fn new(config: &UuidConfig) -> Self {
+        Self { version: config.version }
+    }
+}
+
+
+#[async_trait]
+impl Generator for UuidGenerator {
+    /// Generates a new UUID of the configured version.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        let uuid = match self.version {
+            UuidVersion::V4 => Uuid::new_v4(),
+            UuidVersion::V7 => Uuid::now_v7(),
+        };
+        Ok(uuid.hyphenated().to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generator(version: UuidVersion) -> UuidGenerator {
+        UuidGenerator::new(&UuidConfig { version })
+    }
+
+    #[tokio::test]
+    async fn test_v4_keys_are_valid_and_distinct() {
+        let generator = generator(UuidVersion::V4);
+        let first = generator.generate_key().await.unwrap();
+        let second = generator.generate_key().await.unwrap();
+        assert_eq!(Uuid::parse_str(&first).unwrap().get_version_num(), 4);
+        assert_eq!(Uuid::parse_str(&second).unwrap().get_version_num(), 4);
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_v7_keys_are_valid_and_ordered() {
+        let generator = generator(UuidVersion::V7);
+        let first = generator.generate_key().await.unwrap();
+        let second = generator.generate_key().await.unwrap();
+        assert_eq!(Uuid::parse_str(&first).unwrap().get_version_num(), 7);
+        assert_eq!(first.len(), 36);
+        assert!(first < second);
+    }
+}
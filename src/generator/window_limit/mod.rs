@@ -0,0 +1,211 @@
+//! This module defines a generator wrapper that issues at most a configured number of
+//! keys per time window, e.g. for temporary campaigns.
+//!
+//! Issued keys are counted in Redis under `window:count`. The first key of a window
+//! sets the counter TTL to the window length, so once the window elapses the counter
+//! disappears and generation resumes. The window is fixed, starting at its first key,
+//! and shared by every instance using the same Redis.
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use deadpool_redis::Pool;
+use tonic::async_trait;
+use crate::config::WindowLimitConfig;
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+use crate::generator::redis::{map_redis_error, redis_pool, pooled_connection};
+
+#[cfg(test)]
+use mockall::automock;
+
+/// The Redis key counting the keys issued in the current window.
+const WINDOW_KEY: &str = "window:count";
+
+/// Lua script incrementing the window counter and starting the window on its first hit.
+const HIT_WINDOW_SCRIPT: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+end
+return count
+";
+
+
+/// A trait for the storage counting the keys issued in the current window.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait WindowStore: Debug + Send + Sync {
+    /// Counts one more key in the current window, starting a new window of length
+    /// `window` if none is running.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with the number of keys counted in the window, including this one.
+    async fn hit(&self, window: Duration) -> Result<u64, GeneratorError>;
+}
+
+
+/// A `WindowStore` backed by Redis.
+#[derive(Debug)]
+pub struct RedisWindowStore {
+    pool: Pool,
+}
+
+
+impl RedisWindowStore {
+    /// Creates a new `RedisWindowStore`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The window limit configuration.
+    pub fn new(config: &WindowLimitConfig) -> Result<Self, GeneratorError> {
+        Ok(Self { pool: redis_pool(&config.redis_config)? })
+    }
+}
+
+
+#[async_trait]
+impl WindowStore for RedisWindowStore {
+    async fn hit(&self, window: Duration) -> Result<u64, GeneratorError> {
+        redis::Script::new(HIT_WINDOW_SCRIPT)
+            .key(WINDOW_KEY)
+            .arg(window.as_millis().to_string())
+            .invoke_async(&mut pooled_connection(&self.pool).await?)
+            .await
+            .map_err(map_redis_error)
+    }
+}
+
+
+/// A generator that stops issuing the keys of another generator once `limit` keys
+/// were issued in the current window.
+#[derive(Debug)]
+pub struct WindowLimitGenerator {
+    inner: Arc<dyn Generator>,
+    store: Arc<dyn WindowStore>,
+    limit: u64,
+    window: Duration,
+}
+
+
+impl WindowLimitGenerator {
+    /// Creates a new `WindowLimitGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `store` - The storage counting the issued keys.
+    /// * `limit` - How many keys may be issued per window.
+    /// * `window` - The length of a window.
+    pub fn new(inner: Arc<dyn Generator>, store: Arc<dyn WindowStore>, limit: u64, window: Duration) -> Self {
+        Self { inner, store, limit, window }
+    }
+
+    /// Creates a Redis-backed `WindowLimitGenerator` from its configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `config` - The window limit configuration.
+    pub fn from_config(inner: Arc<dyn Generator>, config: &WindowLimitConfig) -> Result<Self, GeneratorError> {
+        let store = RedisWindowStore::new(config)?;
+        Ok(Self::new(inner, Arc::new(store), config.limit, Duration::from_secs(config.window_secs)))
+    }
+}
+
+
+#[async_trait]
+impl Generator for WindowLimitGenerator {
+    /// Generates a key from the wrapped generator if the window limit is not reached yet.
+    /// Requests over the limit still count, but do not consume keys of the wrapped generator.
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        if self.store.hit(self.window).await? > self.limit {
+            return Err(GeneratorError::WindowLimitExceeded { limit: self.limit });
+        }
+        self.inner.generate_key().await
+    }
+
+    /// Counts every key of the batch against the window, and only generates the batch if
+    /// all of them fit under the limit.
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        for _ in 0..count {
+            if self.store.hit(self.window).await? > self.limit {
+                return Err(GeneratorError::WindowLimitExceeded { limit: self.limit });
+            }
+        }
+        self.inner.generate_keys(count).await
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        self.inner.collision_probability(issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.inner.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use crate::generator::MockGenerator;
+
+    /// An in-memory `WindowStore` with the same expiry semantics as Redis.
+    #[derive(Debug, Default)]
+    struct MemoryWindowStore {
+        window: Mutex<Option<(Instant, u64)>>,
+    }
+
+    #[async_trait]
+    impl WindowStore for MemoryWindowStore {
+        async fn hit(&self, window: Duration) -> Result<u64, GeneratorError> {
+            let mut current = self.window.lock().unwrap();
+            let (started, count) = match *current {
+                Some((started, count)) if started.elapsed() < window => (started, count + 1),
+                _ => (Instant::now(), 1),
+            };
+            *current = Some((started, count));
+            Ok(count)
+        }
+    }
+
+    fn limited(limit: u64, window: Duration) -> WindowLimitGenerator {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().returning(|| Ok("aaaaaaa1".to_string()));
+        WindowLimitGenerator::new(Arc::new(mock_gen), Arc::new(MemoryWindowStore::default()), limit, window)
+    }
+
+    #[tokio::test]
+    async fn test_limit_triggers_within_window() {
+        let generator = limited(2, Duration::from_secs(60));
+        assert!(generator.generate_key().await.is_ok());
+        assert!(generator.generate_key().await.is_ok());
+        assert_eq!(generator.generate_key().await, Err(GeneratorError::WindowLimitExceeded { limit: 2 }));
+    }
+
+    #[tokio::test]
+    async fn test_limit_resets_after_window() {
+        let generator = limited(1, Duration::from_millis(50));
+        assert!(generator.generate_key().await.is_ok());
+        assert!(generator.generate_key().await.is_err());
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(generator.generate_key().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_requests_do_not_consume_keys() {
+        let mut store = MockWindowStore::new();
+        store.expect_hit().returning(|_| Ok(6));
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().never();
+        let generator = WindowLimitGenerator::new(Arc::new(mock_gen), Arc::new(store), 5, Duration::from_secs(60));
+        assert!(generator.generate_key().await.is_err());
+    }
+}
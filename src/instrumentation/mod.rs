@@ -0,0 +1,150 @@
+//! This module installs the metrics recorder selected by `METRICS_BACKEND`.
+//!
+//! The service records metrics through the `metrics` facade only, so the same
+//! instrumentation feeds whichever backend is installed here. With no backend the
+//! facade discards every measurement.
+use std::net::{Ipv6Addr, SocketAddr};
+use metrics_exporter_prometheus::{BuildError, ExporterFuture, PrometheusBuilder, PrometheusRecorder};
+use metrics_exporter_statsd::{StatsdBuilder, StatsdError, StatsdRecorder};
+use crate::config::{MetricsConfig, PrometheusConfig, StatsdConfig};
+
+/// The number of keys handed out by the `GenerateKey` RPC.
+pub const KEYS_GENERATED_TOTAL: &str = "keygen_keys_generated_total";
+/// The number of `GenerateKey` calls that failed, labeled by the `code` of the `GeneratorError`.
+pub const GENERATE_ERRORS_TOTAL: &str = "keygen_generate_errors_total";
+/// The time `GenerateKey` calls took to generate a key, in seconds.
+pub const GENERATE_DURATION_SECONDS: &str = "keygen_generate_duration_seconds";
+/// The number of retry or re-roll loops that gave up, labeled by `reason`.
+pub const RETRY_EXHAUSTED_TOTAL: &str = "keygen_retry_exhausted_total";
+/// The estimated probability that two of the keys handed out so far collide, labeled by `backend`.
+pub const COLLISION_PROBABILITY: &str = "keygen_collision_probability";
+
+/// The `reason` of a blocklist re-roll loop giving up.
+pub const REASON_BLOCKLIST: &str = "blocklist";
+/// The `reason` of a key filter (`KEY_REJECT_REGEX`, `KEY_DENY_PREFIXES`) re-roll loop giving up.
+pub const REASON_KEY_FILTER: &str = "key_filter";
+/// The `reason` of a uniqueness service (`UNIQUENESS_SERVICE_URL`) re-roll loop giving up.
+pub const REASON_UNIQUENESS: &str = "uniqueness";
+/// The `reason` of a `RANDOM_DEDUP_WINDOW` re-roll loop giving up.
+pub const REASON_DEDUP: &str = "dedup";
+
+
+/// Installs the global metrics recorder for the configured backend.
+///
+/// # Arguments
+///
+/// * `config` - The metrics configuration.
+///
+/// # Returns
+///
+/// An error if the recorder cannot be built or a global recorder is already installed.
+pub fn install_recorder(config: &MetricsConfig) -> Result<(), Box<dyn std::error::Error>> {
+    match config {
+        MetricsConfig::None => Ok(()),
+        MetricsConfig::Statsd(statsd_config) => {
+            metrics::set_global_recorder(statsd_recorder(statsd_config)?)?;
+            Ok(())
+        }
+        MetricsConfig::Prometheus(prometheus_config) => {
+            let (recorder, exporter) = prometheus_recorder(prometheus_config)?;
+            tokio::spawn(exporter);
+            metrics::set_global_recorder(recorder)?;
+            Ok(())
+        }
+    }
+}
+
+
+/// Counts a retry or re-roll loop giving up.
+///
+/// # Arguments
+///
+/// * `reason` - The loop that gave up, one of the `REASON_*` constants.
+pub fn record_retry_exhausted(reason: &'static str) {
+    metrics::counter!(RETRY_EXHAUSTED_TOTAL, "reason" => reason).increment(1);
+}
+
+
+/// Builds a recorder pushing metrics to a StatsD agent over UDP.
+///
+/// # Arguments
+///
+/// * `config` - The StatsD configuration.
+pub fn statsd_recorder(config: &StatsdConfig) -> Result<StatsdRecorder, StatsdError> {
+    StatsdBuilder::from(config.host.clone(), config.port).build(Some(&config.prefix))
+}
+
+
+/// Builds a recorder together with the HTTP server exposing its metrics at `/metrics`.
+/// The server only runs once the returned future is spawned, from within a Tokio runtime.
+///
+/// # Arguments
+///
+/// * `config` - The Prometheus configuration.
+pub fn prometheus_recorder(config: &PrometheusConfig) -> Result<(PrometheusRecorder, ExporterFuture), BuildError> {
+    PrometheusBuilder::new()
+        .with_http_listener(SocketAddr::from((Ipv6Addr::UNSPECIFIED, config.port)))
+        .build()
+}
+
+
+/// Runs `future` on a current-thread runtime with a local debugging recorder and
+/// returns how many times `RETRY_EXHAUSTED_TOTAL` was incremented for `reason`.
+#[cfg(test)]
+pub(crate) fn retry_exhausted_count<F: std::future::Future>(reason: &str, future: F) -> u64 {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    metrics::with_local_recorder(&recorder, || runtime.block_on(future));
+    snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .filter(|(key, ..)| key.key().name() == RETRY_EXHAUSTED_TOTAL)
+        .filter(|(key, ..)| key.key().labels().any(|label| label.key() == "reason" && label.value() == reason))
+        .map(|(.., value)| match value {
+            DebugValue::Counter(count) => count,
+            _ => 0,
+        })
+        .sum()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    #[test]
+    fn test_statsd_emits_counter() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listener.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let config = StatsdConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            prefix: "test".to_string(),
+        };
+
+        let recorder = statsd_recorder(&config).unwrap();
+        metrics::with_local_recorder(&recorder, || metrics::counter!(KEYS_GENERATED_TOTAL).increment(1));
+        // Dropping the recorder flushes its buffered sink.
+        drop(recorder);
+
+        let mut buf = [0u8; 1024];
+        let len = listener.recv(&mut buf).unwrap();
+        let payload = String::from_utf8_lossy(&buf[..len]);
+        assert!(payload.lines().any(|line| line.starts_with("test.keygen_keys_generated_total:1|c")), "{payload}");
+    }
+
+    #[test]
+    fn test_retry_exhausted_is_labeled() {
+        let count = retry_exhausted_count(REASON_BLOCKLIST, async {
+            record_retry_exhausted(REASON_BLOCKLIST);
+            record_retry_exhausted(REASON_KEY_FILTER);
+        });
+        assert_eq!(count, 1);
+    }
+}
@@ -0,0 +1,248 @@
+//! This module defines the key-issuance ledger, which counts issued keys per
+//! calendar day in Redis (`issued:YYYY-MM-DD`).
+//!
+//! Days are computed in the configured timezone, so a key issued at 23:30 UTC is
+//! counted on the next day for a timezone ahead of UTC.
+use std::fmt::Debug;
+use std::sync::Arc;
+use chrono::{DateTime, NaiveDate, Utc};
+use chrono_tz::Tz;
+use deadpool_redis::Pool;
+use tonic::async_trait;
+use tracing::warn;
+use crate::config::{LedgerConfig, RedisConfig};
+use crate::generator::Generator;
+use crate::generator::error::GeneratorError;
+use crate::generator::redis::{map_redis_error, pooled_connection, redis_pool};
+
+#[cfg(test)]
+use mockall::automock;
+
+
+/// A trait for the storage backing the ledger counters.
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait LedgerStore: Debug + Send + Sync {
+    /// Increments the counter stored under `key`.
+    async fn increment(&self, key: &str) -> Result<(), GeneratorError>;
+
+    /// Reads the counters stored under `keys`, returning 0 for missing ones.
+    #[allow(dead_code)]
+    async fn counts(&self, keys: Vec<String>) -> Result<Vec<u128>, GeneratorError>;
+}
+
+
+/// A `LedgerStore` backed by Redis.
+#[derive(Debug)]
+pub struct RedisLedgerStore {
+    pool: Pool,
+}
+
+
+impl RedisLedgerStore {
+    /// Creates a new `RedisLedgerStore`.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The Redis configuration.
+    pub fn new(config: &RedisConfig) -> Result<Self, GeneratorError> {
+        Ok(Self { pool: redis_pool(config)? })
+    }
+}
+
+
+#[async_trait]
+impl LedgerStore for RedisLedgerStore {
+    async fn increment(&self, key: &str) -> Result<(), GeneratorError> {
+        redis::cmd("INCR")
+            .arg(key)
+            .query_async::<()>(&mut pooled_connection(&self.pool).await?)
+            .await
+            .map_err(map_redis_error)
+    }
+
+    async fn counts(&self, keys: Vec<String>) -> Result<Vec<u128>, GeneratorError> {
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut pooled_connection(&self.pool).await?)
+            .await
+            .map_err(map_redis_error)?;
+        values
+            .into_iter()
+            .map(|value| match value {
+                Some(value) => value
+                    .parse::<u128>()
+                    .map_err(|_| GeneratorError::UnknownError(format!("Invalid ledger value: {value}"))),
+                None => Ok(0),
+            })
+            .collect()
+    }
+}
+
+
+/// Counts issued keys per calendar day.
+#[derive(Debug)]
+pub struct IssuanceLedger {
+    store: Arc<dyn LedgerStore>,
+    timezone: Tz,
+}
+
+
+impl IssuanceLedger {
+    /// Creates a new `IssuanceLedger`.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The storage for the counters.
+    /// * `timezone` - The timezone used to decide the calendar day.
+    pub fn new(store: Arc<dyn LedgerStore>, timezone: Tz) -> Self {
+        Self { store, timezone }
+    }
+
+    /// Creates a Redis-backed `IssuanceLedger` from its configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The ledger configuration.
+    pub fn from_config(config: &LedgerConfig) -> Result<Self, GeneratorError> {
+        let store = RedisLedgerStore::new(&config.redis_config)?;
+        Ok(Self::new(Arc::new(store), config.timezone))
+    }
+
+    /// Returns the calendar day of `instant` in the ledger timezone.
+    pub fn day_of(&self, instant: DateTime<Utc>) -> NaiveDate {
+        instant.with_timezone(&self.timezone).date_naive()
+    }
+
+    /// Records one key issued at `instant`.
+    pub async fn record_at(&self, instant: DateTime<Utc>) -> Result<(), GeneratorError> {
+        self.store.increment(&ledger_key(self.day_of(instant))).await
+    }
+
+    /// Returns the number of keys issued on each day from `from` to `to`, both inclusive.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` with one `(day, count)` pair per day, or a `GeneratorError`.
+    // TODO: serve this from a range-query RPC once rust-proto-pkg defines its messages.
+    #[allow(dead_code)]
+    pub async fn totals(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<(NaiveDate, u128)>, GeneratorError> {
+        let days: Vec<NaiveDate> = from.iter_days().take_while(|day| *day <= to).collect();
+        if days.is_empty() {
+            return Ok(Vec::new());
+        }
+        let keys: Vec<String> = days.iter().map(|day| ledger_key(*day)).collect();
+        let counts = self.store.counts(keys).await?;
+        Ok(days.into_iter().zip(counts).collect())
+    }
+}
+
+
+/// Returns the Redis key holding the counter for `day`.
+pub fn ledger_key(day: NaiveDate) -> String {
+    format!("issued:{}", day.format("%Y-%m-%d"))
+}
+
+
+/// A generator that records every issued key in an `IssuanceLedger`.
+/// Ledger failures are logged and never fail key generation.
+#[derive(Debug)]
+pub struct LedgerGenerator {
+    inner: Arc<dyn Generator>,
+    ledger: IssuanceLedger,
+}
+
+
+impl LedgerGenerator {
+    /// Creates a new `LedgerGenerator`.
+    ///
+    /// # Arguments
+    ///
+    /// * `inner` - The generator producing the keys.
+    /// * `ledger` - The ledger recording them.
+    pub fn new(inner: Arc<dyn Generator>, ledger: IssuanceLedger) -> Self {
+        Self { inner, ledger }
+    }
+}
+
+
+#[async_trait]
+impl Generator for LedgerGenerator {
+    async fn generate_key(&self) -> Result<String, GeneratorError> {
+        let key = self.inner.generate_key().await?;
+        if let Err(err) = self.ledger.record_at(Utc::now()).await {
+            warn!(error_code = err.code(), "Failed to record issued key in the ledger: {err}");
+        }
+        Ok(key)
+    }
+
+    async fn generate_keys(&self, count: usize) -> Result<Vec<String>, GeneratorError> {
+        let keys = self.inner.generate_keys(count).await?;
+        for _ in &keys {
+            if let Err(err) = self.ledger.record_at(Utc::now()).await {
+                warn!(error_code = err.code(), "Failed to record issued key in the ledger: {err}");
+            }
+        }
+        Ok(keys)
+    }
+
+    fn collision_probability(&self, issued: u128) -> Option<f64> {
+        self.inner.collision_probability(issued)
+    }
+
+    fn is_degraded(&self) -> bool {
+        self.inner.is_degraded()
+    }
+
+    async fn health(&self) -> Result<(), GeneratorError> {
+        self.inner.health().await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use mockall::predicate::eq;
+
+    fn day(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_increments_day_key() {
+        let mut store = MockLedgerStore::new();
+        store.expect_increment().with(eq("issued:2026-10-15")).times(1).returning(|_| Ok(()));
+        let ledger = IssuanceLedger::new(Arc::new(store), Tz::UTC);
+        let instant = Utc.with_ymd_and_hms(2026, 10, 15, 12, 0, 0).unwrap();
+        ledger.record_at(instant).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_day_rollover_uses_timezone() {
+        let ledger = IssuanceLedger::new(Arc::new(MockLedgerStore::new()), Tz::Europe__Madrid);
+        let late_utc = Utc.with_ymd_and_hms(2026, 10, 15, 23, 30, 0).unwrap();
+        assert_eq!(ledger.day_of(late_utc), day(2026, 10, 16));
+        let utc_ledger = IssuanceLedger::new(Arc::new(MockLedgerStore::new()), Tz::UTC);
+        assert_eq!(utc_ledger.day_of(late_utc), day(2026, 10, 15));
+    }
+
+    #[tokio::test]
+    async fn test_totals_for_range() {
+        let mut store = MockLedgerStore::new();
+        store
+            .expect_counts()
+            .withf(|keys: &Vec<String>| keys == &["issued:2026-10-14", "issued:2026-10-15", "issued:2026-10-16"])
+            .returning(|_| Ok(vec![3, 0, 7]));
+        let ledger = IssuanceLedger::new(Arc::new(store), Tz::UTC);
+        let totals = ledger.totals(day(2026, 10, 14), day(2026, 10, 16)).await.unwrap();
+        assert_eq!(totals, vec![(day(2026, 10, 14), 3), (day(2026, 10, 15), 0), (day(2026, 10, 16), 7)]);
+    }
+
+    #[tokio::test]
+    async fn test_totals_for_empty_range() {
+        let ledger = IssuanceLedger::new(Arc::new(MockLedgerStore::new()), Tz::UTC);
+        assert!(ledger.totals(day(2026, 10, 16), day(2026, 10, 14)).await.unwrap().is_empty());
+    }
+}
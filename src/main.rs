@@ -2,45 +2,426 @@
 //! It sets up the server, configures tracing and logging, and starts the
 //! gRPC service.
 
-use tonic::transport::Server;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tonic::transport::{Server, Uri};
 use tokio::{time::Duration, time};
-use tracing::info;
+use tokio::sync::watch;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio_stream::wrappers::TcpListenerStream;
+use tracing::{error, info, warn};
 use rust_otel_setup::otel::OpenTelemetryObject;
 use rust_otel_setup::config as otel_config;
+use rust_proto_pkg::generated::FILE_DESCRIPTOR_SET;
 use rust_proto_pkg::generated::key_generator_service_server::KeyGeneratorServiceServer;
+use tonic_reflection::server::v1::{ServerReflection, ServerReflectionServer};
 use tonic_tracing_opentelemetry::middleware::server::OtelGrpcLayer;
+use crate::config::{GenerationKeyServiceConfig, GeneratorConfig, ServiceConfig};
+use crate::generator::Generator;
 use crate::generator::generator_object::new_key_generation_layer;
+use crate::generator::region_tag::RegionTagGenerator;
+use crate::generator::key_filter::{KeyFilter, KeyFilterGenerator};
+use crate::generator::duplicate_detection::DuplicateDetectorGenerator;
+use crate::generator::window_limit::WindowLimitGenerator;
+use crate::generator::uniqueness::UniquenessCheckGenerator;
+use crate::generator::expansion::KEY_LENGTH;
+use crate::generator::encoding::{install_alphabet, install_encoding};
+use crate::ledger::{IssuanceLedger, LedgerGenerator};
 
 mod generator;
 mod service;
 mod config;
+mod ledger;
+mod instrumentation;
 
 
-// grpcurl  -plaintext -d '{}' -proto v1/key-generator.proto  localhost:8080 tinyurl.v1.KeyGeneratorService/Ping
-// grpcurl  -plaintext -d '{}' -proto key-generator.proto  localhost:8080 tinyurl.v1.KeyGeneratorService/GenerateKey
+/// The exit code used when a listen port is already in use.
+const EXIT_PORT_IN_USE: u8 = 98;
+/// How many ports, starting at the configured one, are tried under `PORT_AUTO_INCREMENT`.
+const PORT_AUTO_INCREMENT_ATTEMPTS: usize = 10;
+/// The time between two probes of the generator backend reported by the health service.
+const HEALTH_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the OTLP collector has to accept a connection when telemetry is required.
+const COLLECTOR_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+
+// grpcurl  -plaintext -d '{}' localhost:8080 tinyurl.v1.KeyGeneratorService/Ping
+// grpcurl  -plaintext -d '{}' localhost:8080 tinyurl.v1.KeyGeneratorService/GenerateKey
+// Without ENABLE_REFLECTION, pass the proto file: -proto v1/key-generator.proto
 
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config = config::GenerationKeyServiceConfig::from_env()?;
-
-    let generator = new_key_generation_layer(&config.generator_config).await?;
-    let generator_service = service::CustomKeyGeneratorService::new(generator).await?;
-
-    let otl_object = OpenTelemetryObject::new(&otel_config::LogConfig::from_env()?, &otel_config::TraceConfig::from_env()?, "key-generation-service".into()).await?;
-
-    let addr = format!("[::]:{}", config.listen_port).parse()?;
-    info!("stating server on {addr}");
-    let gs = KeyGeneratorServiceServer::new(generator_service);
-
-    Server::builder()
-        .layer(OtelGrpcLayer::default())
-        .add_service(gs)
-        .serve_with_shutdown(addr, async {
-            tokio::signal::ctrl_c().await.expect("failed to install CTRL+C signal handler");
-            time::sleep(Duration::from_secs(1)).await;
-            otl_object.stop().expect("Failed to shut down tracer");
-        })
-        .await?;
+async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let config = GenerationKeyServiceConfig::load()?;
+    install_encoding(config.encoding);
+    if let Some(alphabet) = &config.alphabet {
+        install_alphabet(alphabet.clone());
+    }
+    let telemetry = start_telemetry(config.telemetry_required, &config.telemetry_endpoint).await;
+    if telemetry.is_err() {
+        install_fallback_subscriber();
+    }
+    let otl_object = apply_telemetry_policy(telemetry, config.telemetry_required)?;
+    config.validate()?;
+    instrumentation::install_recorder(&config.metrics_config)?;
+    if let Some(max_expansions) = config.auto_expand {
+        KEY_LENGTH.enable_auto_expand(max_expansions);
+    }
+    if config.region_tag.is_some() || config.scheme_prefix {
+        KEY_LENGTH.reserve_leading_digit();
+    }
+
+    let mut listeners = vec![(config.listen_port, &config.generator_config)];
+    listeners.extend(config.extra_listeners.iter().map(|listener| (listener.port, &listener.generator_config)));
+
+    let reflection = reflection_service(config.enable_reflection)?;
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let mut servers = JoinSet::new();
+    let mut services = Vec::new();
+    for (port, generator_config) in listeners {
+        let generator = wrap_generator(new_key_generation_layer(generator_config).await?, generator_config, &config)?;
+        let service_config = ServiceConfig { generator_type: generator_config.name(), ..config.service_config.clone() };
+        let generator_service = Arc::new(service::CustomKeyGeneratorService::new(generator, service_config));
+        if config.warmup && matches!(generator_config, GeneratorConfig::MaintenancePool(_)) {
+            info!("skipping warmup, it would consume a maintenance pool key");
+        } else if config.warmup {
+            let key = generator_service.warmup(config.region_tag).await?;
+            info!("warmup generated a valid key: {key}");
+        }
+        let (health_reporter, health_server) = tonic_health::server::health_reporter();
+        generator_service.clone().spawn_health_probe(health_reporter, HEALTH_PROBE_INTERVAL);
+        services.push(generator_service.clone());
+
+        let listener = match bind_listener(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port, config.port_auto_increment).await {
+            Ok(listener) => listener,
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+                error!("{err}: stop the process using it or choose another port");
+                stop_telemetry(otl_object);
+                return Ok(ExitCode::from(EXIT_PORT_IN_USE));
+            }
+            Err(err) => return Err(err.into()),
+        };
+        info!("stating server on {} with the {} generator", listener.local_addr()?, generator_config.name());
+        let mut shutdown = shutdown_rx.clone();
+        servers.spawn(
+            Server::builder()
+                .layer(OtelGrpcLayer::default())
+                .add_service(health_server)
+                .add_optional_service(reflection.clone())
+                .add_service(KeyGeneratorServiceServer::from_arc(generator_service))
+                .serve_with_incoming_shutdown(TcpListenerStream::new(listener), async move {
+                    let _ = shutdown.changed().await;
+                }),
+        );
+    }
+
+    tokio::select! {
+        result = join_servers(&mut servers) => result?,
+        signal = tokio::signal::ctrl_c() => {
+            signal.expect("failed to install CTRL+C signal handler");
+            let timeout = Duration::from_secs(config.shutdown_timeout_secs);
+            info!("shutting down, waiting up to {}s for in-flight requests", timeout.as_secs());
+            let _ = shutdown_tx.send(());
+            drain(&services, &mut servers, timeout).await?;
+        }
+    }
+    stop_telemetry(otl_object);
+    Ok(ExitCode::SUCCESS)
+}
+
+
+/// Flushes and shuts down telemetry, if it was initialized.
+fn stop_telemetry(otl_object: Option<OpenTelemetryObject>) {
+    if let Some(otl_object) = otl_object {
+        otl_object.stop().expect("Failed to shut down tracer");
+    }
+}
+
+
+/// Waits for every server to stop.
+///
+/// # Returns
+///
+/// The first error of a server, if any.
+async fn join_servers(servers: &mut JoinSet<Result<(), tonic::transport::Error>>) -> Result<(), Box<dyn std::error::Error>> {
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
     Ok(())
 }
+
+
+/// Waits for the in-flight requests of every service to complete and for the servers,
+/// which must have been told to stop accepting requests, to close their connections.
+/// Past `timeout`, the remaining requests are abandoned with a warning.
+///
+/// # Arguments
+///
+/// * `services` - The services of the servers.
+/// * `servers` - The servers.
+/// * `timeout` - How long to wait at most.
+///
+/// # Returns
+///
+/// The first error of a server, if any.
+async fn drain(
+    services: &[Arc<service::CustomKeyGeneratorService>],
+    servers: &mut JoinSet<Result<(), tonic::transport::Error>>,
+    timeout: Duration,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let deadline = time::Instant::now() + timeout;
+    for service in services {
+        if !service.drain(deadline.saturating_duration_since(time::Instant::now())).await {
+            warn!("in-flight requests did not complete within {}s, abandoning them", timeout.as_secs());
+            return Ok(());
+        }
+    }
+    match time::timeout_at(deadline, join_servers(servers)).await {
+        Ok(result) => result,
+        Err(_) => {
+            warn!("connections did not close within {}s, abandoning them", timeout.as_secs());
+            Ok(())
+        }
+    }
+}
+
+
+/// Applies the configured wrappers (region tag or scheme prefix, key filter, uniqueness check, window limit,
+/// ledger, duplicate detection) to a generator.
+///
+/// # Arguments
+///
+/// * `generator` - The generator producing the keys.
+/// * `generator_config` - The configuration of `generator`.
+/// * `config` - The service configuration.
+///
+/// # Returns
+///
+/// The wrapped generator, or an error if a wrapper cannot be set up.
+fn wrap_generator(mut generator: Arc<dyn Generator>, generator_config: &GeneratorConfig, config: &GenerationKeyServiceConfig) -> Result<Arc<dyn Generator>, Box<dyn std::error::Error>> {
+    if let Some(tag) = config.region_tag {
+        generator = Arc::new(RegionTagGenerator::new(generator, tag));
+    }
+    if let Some(prefix) = generator_config.scheme_prefix().filter(|_| config.scheme_prefix) {
+        generator = Arc::new(RegionTagGenerator::new(generator, prefix));
+    }
+    if let Some(filter_config) = &config.key_filter_config {
+        let filter = KeyFilter::new(filter_config)?;
+        generator = Arc::new(KeyFilterGenerator::new(generator, filter, filter_config.max_rerolls));
+    }
+    if let (Some(uniqueness_config), GeneratorConfig::Random(_)) = (&config.uniqueness_config, generator_config) {
+        generator = Arc::new(UniquenessCheckGenerator::from_config(generator, uniqueness_config)?);
+    }
+    if let Some(window_limit_config) = &config.window_limit_config {
+        generator = Arc::new(WindowLimitGenerator::from_config(generator, window_limit_config)?);
+    }
+    if let Some(ledger_config) = &config.ledger_config {
+        generator = Arc::new(LedgerGenerator::new(generator, IssuanceLedger::from_config(ledger_config)?));
+    }
+    if let Some(capacity) = config.duplicate_detection {
+        warn!("DEV_DUPLICATE_DETECTION is enabled, it is meant for development only");
+        generator = Arc::new(DuplicateDetectorGenerator::new(generator, capacity));
+    }
+    Ok(generator)
+}
+
+
+/// Builds the gRPC server reflection service, so clients such as `grpcurl` discover
+/// `tinyurl.v1.KeyGeneratorService` without a local proto file.
+///
+/// # Arguments
+///
+/// * `enabled` - Whether reflection is enabled (`ENABLE_REFLECTION`).
+///
+/// # Returns
+///
+/// The reflection service, `None` when it is disabled, or an error if the file
+/// descriptor set cannot be decoded.
+fn reflection_service(enabled: bool) -> Result<Option<ServerReflectionServer<impl ServerReflection>>, tonic_reflection::server::Error> {
+    if !enabled {
+        return Ok(None);
+    }
+    let service = tonic_reflection::server::Builder::configure()
+        .register_encoded_file_descriptor_set(FILE_DESCRIPTOR_SET)
+        .build_v1()?;
+    Ok(Some(service))
+}
+
+
+/// Binds the listener of a gRPC server.
+///
+/// # Arguments
+///
+/// * `ip` - The address to listen on.
+/// * `port` - The configured port.
+/// * `auto_increment` - Whether the following ports are tried when `port` is in use.
+///
+/// # Returns
+///
+/// The bound listener, or an `AddrInUse` error naming the port if every port tried
+/// is already in use.
+async fn bind_listener(ip: IpAddr, port: u16, auto_increment: bool) -> io::Result<TcpListener> {
+    let attempts = if auto_increment { PORT_AUTO_INCREMENT_ATTEMPTS } else { 1 };
+    for candidate in (port..=u16::MAX).take(attempts) {
+        match TcpListener::bind(SocketAddr::new(ip, candidate)).await {
+            Ok(listener) => {
+                if candidate != port {
+                    warn!("port {port} is already in use, listening on port {candidate} instead");
+                }
+                return Ok(listener);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AddrInUse, format!("port {port} is already in use")))
+}
+
+
+/// Initializes OpenTelemetry logging and tracing from the environment.
+async fn init_telemetry() -> Result<OpenTelemetryObject, Box<dyn std::error::Error>> {
+    let log_config = otel_config::LogConfig::from_env()?;
+    let trace_config = otel_config::TraceConfig::from_env()?;
+    Ok(OpenTelemetryObject::new(&log_config, &trace_config, "key-generation-service".into()).await?)
+}
+
+
+/// Initializes telemetry. The exporters connect to the collector lazily, so when telemetry
+/// is required the collector must first accept a connection, otherwise the service would
+/// start without it.
+///
+/// # Arguments
+///
+/// * `required` - Whether telemetry is mandatory (`TELEMETRY_REQUIRED`).
+/// * `endpoint` - The URL of the OTLP collector (`OTEL_EXPORTER_OTLP_ENDPOINT`).
+async fn start_telemetry(required: bool, endpoint: &str) -> Result<OpenTelemetryObject, Box<dyn std::error::Error>> {
+    if required {
+        check_collector(endpoint).await?;
+    }
+    init_telemetry().await
+}
+
+
+/// Checks that the collector at `endpoint` accepts a TCP connection.
+///
+/// # Returns
+///
+/// An error if the URL has no host, or the collector refused or did not answer in time.
+async fn check_collector(endpoint: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let uri: Uri = endpoint.parse()?;
+    let host = uri.host().ok_or_else(|| format!("collector URL {endpoint} has no host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = uri.port_u16().unwrap_or(4317);
+    time::timeout(COLLECTOR_CONNECT_TIMEOUT, TcpStream::connect((host, port)))
+        .await
+        .map_err(|_| format!("collector at {endpoint} did not answer within {}s", COLLECTOR_CONNECT_TIMEOUT.as_secs()))?
+        .map_err(|err| format!("collector at {endpoint} is unreachable: {err}"))?;
+    Ok(())
+}
+
+
+/// Logs to stderr when telemetry could not be initialized, so startup warnings and
+/// errors are not lost. A subscriber installed by a partial initialization is kept.
+fn install_fallback_subscriber() {
+    let _ = tracing_subscriber::fmt().with_writer(io::stderr).try_init();
+}
+
+
+/// Decides whether a telemetry initialization failure aborts startup.
+///
+/// # Arguments
+///
+/// * `result` - The outcome of initializing telemetry.
+/// * `required` - Whether telemetry is mandatory (`TELEMETRY_REQUIRED`).
+///
+/// # Returns
+///
+/// The telemetry object, `None` when it failed but is optional, or an error when it
+/// failed and is required.
+fn apply_telemetry_policy<T>(result: Result<T, Box<dyn std::error::Error>>, required: bool) -> Result<Option<T>, Box<dyn std::error::Error>> {
+    match result {
+        Ok(object) => Ok(Some(object)),
+        Err(err) if required => Err(format!("Telemetry is required but could not be initialized: {err}").into()),
+        Err(err) => {
+            warn!("Telemetry could not be initialized, continuing without it: {err}");
+            Ok(None)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_test::traced_test;
+
+    fn unreachable_collector() -> Result<(), Box<dyn std::error::Error>> {
+        Err("failed to connect to collector at http://localhost:1".into())
+    }
+
+    #[tokio::test]
+    async fn test_port_in_use_is_reported() {
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let taken = TcpListener::bind(SocketAddr::new(localhost, 0)).await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let err = bind_listener(localhost, port, false).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(err.to_string(), format!("port {port} is already in use"));
+    }
+
+    #[tokio::test]
+    async fn test_port_auto_increment() {
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let taken = TcpListener::bind(SocketAddr::new(localhost, 0)).await.unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let listener = bind_listener(localhost, port, true).await.unwrap();
+        assert_ne!(listener.local_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test]
+    async fn test_reflection_lists_the_key_generator_service() {
+        use tokio_stream::StreamExt;
+        use tonic_reflection::pb::v1::server_reflection_client::ServerReflectionClient;
+        use tonic_reflection::pb::v1::server_reflection_request::MessageRequest;
+        use tonic_reflection::pb::v1::server_reflection_response::MessageResponse;
+        use tonic_reflection::pb::v1::ServerReflectionRequest;
+
+        assert!(reflection_service(false).unwrap().is_none());
+        let listener = bind_listener(IpAddr::from([127, 0, 0, 1]), 0, false).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_optional_service(reflection_service(true).unwrap())
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        let mut client = ServerReflectionClient::connect(format!("http://{addr}")).await.unwrap();
+        let request = ServerReflectionRequest { host: String::new(), message_request: Some(MessageRequest::ListServices(String::new())) };
+        let mut responses = client.server_reflection_info(tokio_stream::iter([request])).await.unwrap().into_inner();
+        let response = responses.next().await.unwrap().unwrap();
+        let Some(MessageResponse::ListServicesResponse(list)) = response.message_response else {
+            panic!("unexpected reflection response: {response:?}");
+        };
+        assert!(list.service.iter().any(|service| service.name == "tinyurl.v1.KeyGeneratorService"));
+    }
+
+    #[tokio::test]
+    async fn test_required_telemetry_aborts_startup() {
+        let closed = TcpListener::bind(SocketAddr::new(IpAddr::from([127, 0, 0, 1]), 0)).await.unwrap();
+        let endpoint = format!("http://{}", closed.local_addr().unwrap());
+        drop(closed);
+        let err = apply_telemetry_policy(start_telemetry(true, &endpoint).await, true).unwrap_err();
+        assert!(err.to_string().starts_with(&format!("Telemetry is required but could not be initialized: collector at {endpoint} is unreachable")));
+    }
+
+    #[traced_test]
+    #[test]
+    fn test_optional_telemetry_continues() {
+        assert!(apply_telemetry_policy(unreachable_collector(), false).unwrap().is_none());
+        assert!(logs_contain("Telemetry could not be initialized, continuing without it"));
+        assert!(apply_telemetry_policy(Ok(()), true).unwrap().is_some());
+    }
+}
@@ -1,15 +1,61 @@
 //! This module defines the gRPC service implementation for the key generator.
 use std::sync::Arc;
-use tracing::instrument;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tracing::{error, instrument, warn, Span};
+use tracing::field::Empty;
 use tonic::{async_trait, Request, Response, Status};
+use tonic::metadata::{MetadataMap, MetadataValue};
+use tonic::server::NamedService;
+use tonic_health::ServingStatus;
+use tonic_health::server::HealthReporter;
 use rust_proto_pkg::generated::{GenerateKeyRequest, GenerateKeyResponse, PingRequest, PingResponse};
-use rust_proto_pkg::generated::key_generator_service_server::KeyGeneratorService;
-use crate::generator::Generator;
+use rust_proto_pkg::generated::key_generator_service_server::{KeyGeneratorService, KeyGeneratorServiceServer};
+use crate::config::ServiceConfig;
+use crate::generator::{number_digits, Generator};
+use crate::generator::encoding::{key_encoding, Encoding};
+use crate::generator::expansion::KEY_LENGTH;
+use crate::generator::error::GeneratorError;
+use crate::instrumentation::{COLLISION_PROBABILITY, GENERATE_DURATION_SECONDS, GENERATE_ERRORS_TOTAL, KEYS_GENERATED_TOTAL};
 
 /// `CustomKeyGeneratorService` is the implementation of the `KeyGeneratorService` trait.
 #[derive(Debug)]
 pub struct CustomKeyGeneratorService {
     pub(crate) generator: Arc<dyn Generator>,
+    pub(crate) config: ServiceConfig,
+    pub(crate) in_flight: InFlight,
+    pub(crate) issued: AtomicU64,
+}
+
+
+/// `InFlight` counts the `generate_key` calls being handled, so shutdown can wait for them.
+#[derive(Debug, Default)]
+pub struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+
+/// Counts a call as in flight until it is dropped.
+struct InFlightGuard<'a>(&'a InFlight);
+
+
+impl InFlight {
+    /// Counts a call as in flight until the returned guard is dropped.
+    fn enter(&self) -> InFlightGuard<'_> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        InFlightGuard(self)
+    }
+}
+
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
 }
 
 
@@ -19,15 +65,149 @@ impl CustomKeyGeneratorService {
     /// # Arguments
     ///
     /// * `generator` - The generator used for the service.
+    /// * `config` - The options of the service layer.
+    pub fn new(generator: Arc<dyn Generator>, config: ServiceConfig) -> Self {
+        Self { generator, config, in_flight: InFlight::default(), issued: AtomicU64::default() }
+    }
+
+    /// Waits until no `generate_key` call is in flight. The server must have stopped
+    /// accepting requests, otherwise new calls can keep it busy.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - How long to wait at most.
+    ///
+    /// # Returns
+    ///
+    /// Whether every call completed within `timeout`.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        let idle = async {
+            loop {
+                let notified = self.in_flight.idle.notified();
+                if self.in_flight.count.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, idle).await.is_ok()
+    }
+
+    /// Generates one throwaway key through the whole generation pipeline and checks
+    /// its format, so a misconfiguration fails at startup instead of on the first
+    /// request. The key is consumed.
+    ///
+    /// The blocklist is not checked again: the random generator, its only user, already
+    /// re-rolls blocked values on every draw.
+    ///
+    /// # Arguments
+    ///
+    /// * `region_tag` - The region tag every key must start with, if configured.
     ///
     /// # Returns
     ///
-    /// A `Result` containing a new `CustomKeyGeneratorService` or an error.
-    pub async fn new(generator: Arc<dyn Generator>) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(Self { generator })
+    /// A `Result` with the generated key, or a `GeneratorError` naming the failed check.
+    pub async fn warmup(&self, region_tag: Option<char>) -> Result<String, GeneratorError> {
+        let key = self.generator.generate_key().await?;
+        check_warmup_key(&key, key_encoding(), region_tag)?;
+        Ok(key)
+    }
+
+    /// Probes the backend of the generator, e.g. with a `PING` to Redis.
+    ///
+    /// # Returns
+    ///
+    /// `ServingStatus::Serving` if the backend answered, otherwise `ServingStatus::NotServing`.
+    pub async fn serving_status(&self) -> ServingStatus {
+        match self.generator.health().await {
+            Ok(()) => ServingStatus::Serving,
+            Err(err) => {
+                warn!(error_code = err.code(), "Health probe failed: {err}");
+                ServingStatus::NotServing
+            }
+        }
+    }
+
+    /// Probes the backend of the generator and reports the result through the standard
+    /// `grpc.health.v1.Health` service, for the whole server and the key generator service.
+    ///
+    /// # Arguments
+    ///
+    /// * `reporter` - The reporter of the health service.
+    pub async fn report_health(&self, reporter: &HealthReporter) {
+        let status = self.serving_status().await;
+        reporter.set_service_status("", status).await;
+        reporter.set_service_status(KeyGeneratorServiceServer::<Self>::NAME, status).await;
+    }
+
+    /// Records the collision probability the generator estimates for the keys handed
+    /// out so far, if it can estimate one.
+    pub fn record_collision_probability(&self) {
+        let issued = self.issued.load(Ordering::Relaxed);
+        if let Some(probability) = self.generator.collision_probability(issued.into()) {
+            metrics::gauge!(COLLISION_PROBABILITY, "backend" => self.config.generator_type).set(probability);
+        }
+    }
+
+    /// Spawns the task probing the backend of the generator and recording the collision
+    /// probability every `interval`.
+    ///
+    /// # Arguments
+    ///
+    /// * `reporter` - The reporter of the health service.
+    /// * `interval` - The time between two probes.
+    pub fn spawn_health_probe(self: Arc<Self>, reporter: HealthReporter, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                self.report_health(&reporter).await;
+                self.record_collision_probability();
+            }
+        });
+    }
+
+    /// Adds the instance id, generator type and degraded flag to the response metadata.
+    fn add_metadata(&self, metadata: &mut MetadataMap) {
+        if let Ok(instance_id) = self.config.instance_id.parse() {
+            metadata.insert("x-instance-id", instance_id);
+        }
+        metadata.insert("x-generator-type", MetadataValue::from_static(self.config.generator_type));
+        let degraded = if self.generator.is_degraded() { "true" } else { "false" };
+        metadata.insert("x-degraded", MetadataValue::from_static(degraded));
+    }
+}
+
+/// Checks the length, alphabet, region tag and check symbol of a warmup key.
+///
+/// # Arguments
+///
+/// * `key` - The warmup key.
+/// * `encoding` - The key encoding.
+/// * `region_tag` - The region tag the key must start with, if configured.
+///
+/// # Returns
+///
+/// A `GeneratorError::InvalidKey` naming the failed check, if any.
+fn check_warmup_key(key: &str, encoding: Encoding, region_tag: Option<char>) -> Result<(), GeneratorError> {
+    let invalid = |check| GeneratorError::InvalidKey { key: key.to_string(), check };
+    if key.chars().count() != encoding.key_length(KEY_LENGTH.current_digits(number_digits())) {
+        return Err(invalid("length"));
+    }
+    if !key.chars().all(|c| encoding.is_key_char(c)) {
+        return Err(invalid("alphabet"));
+    }
+    if region_tag.is_some_and(|tag| !key.starts_with(tag)) {
+        return Err(invalid("region tag"));
+    }
+    let digits: String = key.chars().skip(KEY_LENGTH.reserved_digits()).collect();
+    match encoding.decode(&digits) {
+        Err(GeneratorError::InvalidKey { check, .. }) => Err(invalid(check)),
+        result => result.map(|_| ()),
     }
 }
 
+
 #[async_trait]
 impl KeyGeneratorService for CustomKeyGeneratorService {
     /// Handles the Ping RPC.
@@ -43,11 +223,31 @@ impl KeyGeneratorService for CustomKeyGeneratorService {
         Ok::<Response<PingResponse>, Status>(Response::new(reply))
     }
 
-    /// Handles the GenerateKey RPC.
-    #[instrument(level = "info", target = "service::generate_key", skip(self, _request))]
+    /// Handles the GenerateKey RPC. The span records the `GENERATOR_TYPE` as `backend` and
+    /// whether keys are served from a counter buffer as `buffered`, then the length of the
+    /// key as `key_length`, or the `GeneratorError` variant as `error`.
+    #[instrument(level = "info", target = "service::generate_key", skip(self, _request), fields(backend = Empty, buffered = Empty, key_length = Empty, error = Empty))]
     async fn generate_key(&self, _request: Request<GenerateKeyRequest>) -> Result<Response<GenerateKeyResponse>, Status> {
-        let key = self.generator.generate_key().await?;
-        Ok(Response::new(GenerateKeyResponse{key}))
+        let _in_flight = self.in_flight.enter();
+        let span = Span::current();
+        span.record("backend", self.config.generator_type);
+        span.record("buffered", self.config.generator_type == "buffered_redis");
+        let started = Instant::now();
+        let key = self.generator.generate_key().await;
+        metrics::histogram!(GENERATE_DURATION_SECONDS).record(started.elapsed().as_secs_f64());
+        let key = key.inspect_err(|err| {
+            span.record("error", err.variant());
+            error!(error_code = err.code(), "Key generation failed: {err}");
+            metrics::counter!(GENERATE_ERRORS_TOTAL, "code" => err.code()).increment(1);
+        })?;
+        span.record("key_length", key.chars().count());
+        metrics::counter!(KEYS_GENERATED_TOTAL).increment(1);
+        self.issued.fetch_add(1, Ordering::Relaxed);
+        let mut response = Response::new(GenerateKeyResponse{key});
+        if self.config.response_metadata {
+            self.add_metadata(response.metadata_mut());
+        }
+        Ok(response)
     }
 }
 
@@ -55,12 +255,16 @@ impl KeyGeneratorService for CustomKeyGeneratorService {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tracing_test::traced_test;
+    use tonic_health::pb::HealthCheckRequest;
+    use tonic_health::pb::health_check_response::ServingStatus as ProtoServingStatus;
+    use tonic_health::pb::health_server::Health;
+    use tonic_health::server::HealthService;
     use crate::generator::MockGenerator;
-    use crate::generator::error::GeneratorError;
 
     pub fn get_generator() -> CustomKeyGeneratorService {
         let generator = Arc::new(MockGenerator::new());
-        CustomKeyGeneratorService { generator }
+        CustomKeyGeneratorService::new(generator, ServiceConfig::default())
     }
 
     #[tokio::test]
@@ -75,19 +279,262 @@ mod tests {
     async fn test_generate_key_ok() {
         let mut mock_gen = MockGenerator::new();
         mock_gen.expect_generate_key().return_const(Ok("abcdef12".to_string()));
-        let service = CustomKeyGeneratorService { generator: Arc::new(mock_gen) };
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default());
         let request = Request::new(GenerateKeyRequest {});
         let response = service.generate_key(request).await.unwrap();
         assert_eq!(response.into_inner().key, "abcdef12");
     }
 
+    #[traced_test]
+    #[tokio::test]
+    async fn test_generate_key_err_logs_code() {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().return_const(Err(GeneratorError::ReadOnly));
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default());
+        service.generate_key(Request::new(GenerateKeyRequest {})).await.unwrap_err();
+        assert!(logs_contain("error_code=\"KGEN-REDIS-003\""));
+    }
+
+    #[test]
+    fn test_collision_probability_gauge_counts_issued_keys() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().return_const(Ok("abcdef12".to_string()));
+        mock_gen.expect_collision_probability().with(mockall::predicate::eq(2)).return_const(Some(0.5));
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default());
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        metrics::with_local_recorder(&recorder, || runtime.block_on(async {
+            service.generate_key(Request::new(GenerateKeyRequest {})).await.unwrap();
+            service.generate_key(Request::new(GenerateKeyRequest {})).await.unwrap();
+            service.record_collision_probability();
+        }));
+        let gauge = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .find(|(key, ..)| key.key().name() == COLLISION_PROBABILITY)
+            .map(|(.., value)| value);
+        assert!(matches!(gauge, Some(DebugValue::Gauge(probability)) if probability.0 == 0.5));
+    }
+
+    /// Captures the fields recorded on `generate_key` spans.
+    #[derive(Clone, Default)]
+    struct SpanFields(Arc<std::sync::Mutex<std::collections::HashMap<&'static str, String>>>);
+
+    impl tracing::field::Visit for SpanFields {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.lock().unwrap().insert(field.name(), format!("{value:?}"));
+        }
+
+        fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+            self.0.lock().unwrap().insert(field.name(), value.to_string());
+        }
+    }
+
+    impl<S> tracing_subscriber::Layer<S> for SpanFields
+    where
+        S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    {
+        fn on_record(&self, id: &tracing::span::Id, values: &tracing::span::Record<'_>, ctx: tracing_subscriber::layer::Context<'_, S>) {
+            if ctx.span(id).is_some_and(|span| span.name() == "generate_key") {
+                values.record(&mut self.clone());
+            }
+        }
+    }
+
+    async fn generate_key_span_fields(generator_type: &'static str, result: Result<String, GeneratorError>) -> std::collections::HashMap<&'static str, String> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().return_const(result);
+        let config = ServiceConfig { generator_type, ..ServiceConfig::default() };
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), config);
+        let fields = SpanFields::default();
+        let _subscriber = tracing::subscriber::set_default(tracing_subscriber::registry().with(fields.clone()));
+        let _ = service.generate_key(Request::new(GenerateKeyRequest {})).await;
+        let fields = fields.0.lock().unwrap().clone();
+        fields
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_span_records_backend_and_length() {
+        let fields = generate_key_span_fields("redis", Ok("abcdef12".to_string())).await;
+        assert_eq!(fields.get("backend").map(String::as_str), Some("redis"));
+        assert_eq!(fields.get("buffered").map(String::as_str), Some("false"));
+        assert_eq!(fields.get("key_length").map(String::as_str), Some("8"));
+        assert!(!fields.contains_key("error"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_span_records_buffered_generator() {
+        let fields = generate_key_span_fields("buffered_redis", Ok("abcdef12".to_string())).await;
+        assert_eq!(fields.get("backend").map(String::as_str), Some("buffered_redis"));
+        assert_eq!(fields.get("buffered").map(String::as_str), Some("true"));
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_span_records_error_variant() {
+        let fields = generate_key_span_fields("redis", Err(GeneratorError::ReadOnly)).await;
+        assert_eq!(fields.get("backend").map(String::as_str), Some("redis"));
+        assert_eq!(fields.get("error").map(String::as_str), Some("ReadOnly"));
+        assert!(!fields.contains_key("key_length"));
+    }
+
     #[tokio::test]
     async fn test_generate_key_err() {
         let mut mock_gen = MockGenerator::new();
         mock_gen.expect_generate_key().return_const(Err(GeneratorError::ConnectionError));
-        let service = CustomKeyGeneratorService { generator: Arc::new(mock_gen) };
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default());
         let request = Request::new(GenerateKeyRequest {});
         let response = service.generate_key(request).await.unwrap_err();
         assert_eq!(response.code(), tonic::Code::Unavailable);
     }
+
+    /// A generator taking a while to answer.
+    #[derive(Debug)]
+    struct SlowGenerator;
+
+    #[async_trait]
+    impl Generator for SlowGenerator {
+        async fn generate_key(&self) -> Result<String, GeneratorError> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok("abcdef12".to_string())
+        }
+    }
+
+    fn slow_service() -> Arc<CustomKeyGeneratorService> {
+        let generator = Arc::new(SlowGenerator);
+        Arc::new(CustomKeyGeneratorService::new(generator, ServiceConfig::default()))
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_in_flight_calls() {
+        let service = slow_service();
+        let pending = tokio::spawn({
+            let service = service.clone();
+            async move { service.generate_key(Request::new(GenerateKeyRequest {})).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!pending.is_finished());
+        assert!(service.drain(Duration::from_secs(5)).await);
+        assert!(pending.is_finished());
+        assert_eq!(pending.await.unwrap().unwrap().into_inner().key, "abcdef12");
+        assert!(service.drain(Duration::ZERO).await);
+    }
+
+    #[tokio::test]
+    async fn test_drain_times_out() {
+        let service = slow_service();
+        let pending = tokio::spawn({
+            let service = service.clone();
+            async move { service.generate_key(Request::new(GenerateKeyRequest {})).await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!service.drain(Duration::from_millis(10)).await);
+        assert!(pending.await.unwrap().is_ok());
+    }
+
+    async fn reported_status(health: Result<(), GeneratorError>) -> i32 {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_health().return_const(health);
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default());
+        let (reporter, _) = tonic_health::server::health_reporter();
+        service.report_health(&reporter).await;
+        let request = Request::new(HealthCheckRequest { service: String::new() });
+        let response = HealthService::from_health_reporter(reporter).check(request).await.unwrap();
+        response.into_inner().status
+    }
+
+    #[tokio::test]
+    async fn test_health_serving() {
+        assert_eq!(reported_status(Ok(())).await, ProtoServingStatus::Serving as i32);
+    }
+
+    #[tokio::test]
+    async fn test_health_not_serving_when_backend_fails() {
+        assert_eq!(reported_status(Err(GeneratorError::ConnectionError)).await, ProtoServingStatus::NotServing as i32);
+    }
+
+    fn service_returning(key: &str) -> CustomKeyGeneratorService {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().return_const(Ok(key.to_string()));
+        CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default())
+    }
+
+    #[test]
+    fn test_prometheus_counts_generated_keys() {
+        use crate::config::PrometheusConfig;
+        use crate::instrumentation::prometheus_recorder;
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let (recorder, exporter) = runtime.block_on(async { prometheus_recorder(&PrometheusConfig { port }) }).unwrap();
+        runtime.spawn(exporter);
+
+        let service = service_returning("abcdef12");
+        metrics::with_local_recorder(&recorder, || {
+            runtime.block_on(service.generate_key(Request::new(GenerateKeyRequest {}))).unwrap();
+        });
+        let body = runtime.block_on(async {
+            reqwest::get(format!("http://127.0.0.1:{port}/metrics")).await.unwrap().text().await.unwrap()
+        });
+        assert!(body.contains("keygen_keys_generated_total 1"));
+        assert!(body.contains("keygen_generate_duration_seconds"));
+    }
+
+    #[tokio::test]
+    async fn test_warmup_ok() {
+        assert_eq!(service_returning("E0000001").warmup(Some('E')).await.unwrap(), "E0000001");
+        assert_eq!(service_returning("00000001").warmup(None).await.unwrap(), "00000001");
+    }
+
+    #[tokio::test]
+    async fn test_warmup_reports_failed_check() {
+        let err = service_returning("0000001").warmup(None).await.unwrap_err();
+        assert_eq!(err, GeneratorError::InvalidKey { key: "0000001".to_string(), check: "length" });
+        let err = service_returning("0000000-").warmup(None).await.unwrap_err();
+        assert_eq!(err, GeneratorError::InvalidKey { key: "0000000-".to_string(), check: "alphabet" });
+        let err = service_returning("00000001").warmup(Some('E')).await.unwrap_err();
+        assert_eq!(err, GeneratorError::InvalidKey { key: "00000001".to_string(), check: "region tag" });
+    }
+
+    #[test]
+    fn test_warmup_checks_the_check_symbol() {
+        let crockford = Encoding::Crockford32 { check_symbol: true };
+        assert_eq!(check_warmup_key("0000016JD", crockford, None), Ok(()));
+        assert_eq!(
+            check_warmup_key("0000016JE", crockford, None),
+            Err(GeneratorError::InvalidKey { key: "0000016JE".to_string(), check: "check symbol" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_response_metadata() {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().return_const(Ok("abcdef12".to_string()));
+        mock_gen.expect_is_degraded().return_const(true);
+        let config = ServiceConfig {
+            response_metadata: true,
+            instance_id: "instance-1".to_string(),
+            generator_type: "redis",
+        };
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), config);
+        let response = service.generate_key(Request::new(GenerateKeyRequest {})).await.unwrap();
+        let metadata = response.metadata();
+        assert_eq!(metadata.get("x-instance-id").unwrap(), "instance-1");
+        assert_eq!(metadata.get("x-generator-type").unwrap(), "redis");
+        assert_eq!(metadata.get("x-degraded").unwrap(), "true");
+    }
+
+    #[tokio::test]
+    async fn test_generate_key_without_metadata() {
+        let mut mock_gen = MockGenerator::new();
+        mock_gen.expect_generate_key().return_const(Ok("abcdef12".to_string()));
+        let service = CustomKeyGeneratorService::new(Arc::new(mock_gen), ServiceConfig::default());
+        let response = service.generate_key(Request::new(GenerateKeyRequest {})).await.unwrap();
+        assert!(response.metadata().get("x-generator-type").is_none());
+    }
 }